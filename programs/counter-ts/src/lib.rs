@@ -1,3 +1,6 @@
+// anchor 0.31's #[program] expansion still calls the deprecated AccountInfo::realloc
+#![allow(deprecated)]
+
 use anchor_lang::prelude::*;  //importing everything from the anchor
 
 declare_id!("74QZ1uTUKCPsao19wAtRRxxQ441PeejhkAZBH7nw9EEN");
@@ -9,13 +12,24 @@ pub mod counter_ts {
     pub fn initialize(ctx: Context<Initialize>) -> Result<()> {
         let counter_account =  &mut ctx.accounts.counter_account ;
         counter_account.count = 0 ;
-        
+
+        emit!(CounterInitialized {
+            counter: counter_account.key(),
+            user: ctx.accounts.user.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
         Ok(())
     }
 
     pub fn incerment(ctx: Context<UpdateCounter>) -> Result<()> {
        let counter_account = &mut ctx.accounts.counter_account ; 
        counter_account.count +=1 ;
+
+       emit!(CounterIncremented {
+           counter: counter_account.key(),
+           count: counter_account.count,
+           timestamp: Clock::get()?.unix_timestamp,
+       });
        Ok(())
     }
 }
@@ -38,3 +52,18 @@ pub struct UpdateCounter<'info> {
 pub struct Counter {
     pub count :i64,
 }
+
+#[event]
+pub struct CounterInitialized {
+    pub counter: Pubkey,
+    pub user: Pubkey,
+    pub timestamp: i64,
+}
+
+// `incerment` takes no signer, so there is no actor to report
+#[event]
+pub struct CounterIncremented {
+    pub counter: Pubkey,
+    pub count: i64,
+    pub timestamp: i64,
+}
@@ -1,268 +1,2822 @@
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, TokenAccount, Token, Transfer, Mint};
+use anchor_lang::solana_program::{bpf_loader_upgradeable, keccak};
+use anchor_spl::associated_token::{get_associated_token_address, AssociatedToken};
+use anchor_spl::token::{self, Burn, Mint, MintTo, TokenAccount, Token};
+use anchor_spl::token_2022::spl_token_2022::extension::transfer_fee::TransferFeeConfig;
+use anchor_spl::token_2022::spl_token_2022::extension::{BaseStateWithExtensions, ExtensionType, StateWithExtensions};
+use anchor_spl::token_interface::{self, CloseAccount, TokenInterface, TransferChecked};
+#[cfg(feature = "fixed-owner")]
 use std::str::FromStr;
 
 declare_id!("Havovdums4jVo6HwPj6iUSMLtfmaEHeBNhPBrDgDrWZy");
 
-const USDC_MINT: &str = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
+// Keeps a page small enough to fit in a single return-data payload for `list_vaults`.
+pub const VAULTS_PER_PAGE: usize = 30;
+
+// Same return-data bound as registry pages, applied to the vaults a single wallet can hold positions in.
+pub const MAX_POSITIONS_PER_USER: usize = 30;
+
+// And to the vaults a single manager can run at once.
+pub const MAX_VAULTS_PER_MANAGER: usize = 30;
+
+pub const MAX_ACCEPTED_MINTS: usize = 16;
+
+// Deposits tracked individually per pool before the oldest lots start merging.
+pub const MAX_LOTS: usize = 16;
+
+pub const BPS_DENOMINATOR: u128 = 10_000;
+pub const SECONDS_PER_YEAR: u128 = 365 * 24 * 60 * 60;
+// High-water marks are stored as assets per share at this scale
+pub const PRICE_SCALE: u128 = 1_000_000_000;
 
 #[program]
 pub mod factory {
     use super::*;
     
     pub fn initialize_factory(ctx: Context<InitializeFactory>) -> Result<()> {
+        require_deployer(&ctx.accounts.owner.key(), &ctx.accounts.program_data)?;
+
         let factory = &mut ctx.accounts.factory;
         factory.owner = ctx.accounts.owner.key();
         factory.vault_count = 0;
-        factory.vaults = Vec::new();
-        factory.vault_managers = Vec::new();
+        factory.page_count = 0;
+        factory.max_management_fee_bps = 0;
+        factory.max_performance_fee_bps = 0;
+        factory.deposits_paused = false;
+        factory.withdrawals_paused = false;
+        factory.pending_owner = None;
+
+        emit!(FactoryInitialized {
+            factory: factory.key(),
+            owner: factory.owner,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn set_factory_pause(ctx: Context<SetFactoryPause>, deposits_paused: bool, withdrawals_paused: bool) -> Result<()> {
+        let factory = &mut ctx.accounts.factory;
+        require_keys_eq!(ctx.accounts.owner.key(), factory.owner, CustomError::Unauthorized);
+
+        factory.deposits_paused = deposits_paused;
+        factory.withdrawals_paused = withdrawals_paused;
+
+        emit!(FactoryPauseUpdated {
+            factory: factory.key(),
+            owner: factory.owner,
+            deposits_paused,
+            withdrawals_paused,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn set_vault_pause(ctx: Context<SetVaultPause>, deposits_paused: bool, withdrawals_paused: bool) -> Result<()> {
+        let authority = ctx.accounts.authority.key();
+        let vault = &mut ctx.accounts.vault;
+        require!(
+            authority == vault.manager || authority == ctx.accounts.factory.owner,
+            CustomError::Unauthorized
+        );
+
+        vault.deposits_paused = deposits_paused;
+        vault.withdrawals_paused = withdrawals_paused;
+
+        emit!(VaultPauseUpdated {
+            vault: vault.key(),
+            authority,
+            deposits_paused,
+            withdrawals_paused,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn set_fee_limits(ctx: Context<SetFeeLimits>, max_management_fee_bps: u16, max_performance_fee_bps: u16) -> Result<()> {
+        let factory = &mut ctx.accounts.factory;
+        require_keys_eq!(ctx.accounts.owner.key(), factory.owner, CustomError::Unauthorized);
+        require!(max_management_fee_bps as u128 <= BPS_DENOMINATOR, CustomError::FeeTooHigh);
+        require!(max_performance_fee_bps as u128 <= BPS_DENOMINATOR, CustomError::FeeTooHigh);
+
+        factory.max_management_fee_bps = max_management_fee_bps;
+        factory.max_performance_fee_bps = max_performance_fee_bps;
+
+        emit!(FeeLimitsUpdated {
+            factory: factory.key(),
+            owner: factory.owner,
+            max_management_fee_bps,
+            max_performance_fee_bps,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // Ownership moves in two steps so a mistyped key can't take the factory: the owner proposes and
+    // the new key has to sign `accept_authority`
+    pub fn propose_authority(ctx: Context<UpdateFactoryAuthority>, new_owner: Pubkey) -> Result<()> {
+        let factory = &mut ctx.accounts.factory;
+        require_keys_eq!(ctx.accounts.authority.key(), factory.owner, CustomError::Unauthorized);
+        require_keys_neq!(new_owner, Pubkey::default(), CustomError::InvalidAuthority);
+
+        factory.pending_owner = Some(new_owner);
+
+        emit!(AuthorityProposed {
+            account: factory.key(),
+            authority: factory.owner,
+            pending: new_owner,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn accept_authority(ctx: Context<UpdateFactoryAuthority>) -> Result<()> {
+        let factory = &mut ctx.accounts.factory;
+        let pending = factory.pending_owner.ok_or(CustomError::NoPendingAuthority)?;
+        require_keys_eq!(ctx.accounts.authority.key(), pending, CustomError::Unauthorized);
+
+        let previous = factory.owner;
+        factory.owner = pending;
+        factory.pending_owner = None;
+
+        emit!(AuthorityTransferred {
+            account: factory.key(),
+            previous,
+            authority: factory.owner,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn cancel_authority_transfer(ctx: Context<UpdateFactoryAuthority>) -> Result<()> {
+        let factory = &mut ctx.accounts.factory;
+        require_keys_eq!(ctx.accounts.authority.key(), factory.owner, CustomError::Unauthorized);
+        let pending = factory.pending_owner.ok_or(CustomError::NoPendingAuthority)?;
+
+        factory.pending_owner = None;
+
+        emit!(AuthorityTransferCancelled {
+            account: factory.key(),
+            authority: factory.owner,
+            pending,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // Leaves the factory without an owner for good; owner-only instructions can never run again
+    pub fn renounce_authority(ctx: Context<UpdateFactoryAuthority>) -> Result<()> {
+        let factory = &mut ctx.accounts.factory;
+        require_keys_eq!(ctx.accounts.authority.key(), factory.owner, CustomError::Unauthorized);
+
+        let previous = factory.owner;
+        factory.owner = Pubkey::default();
+        factory.pending_owner = None;
+
+        emit!(AuthorityRenounced {
+            account: factory.key(),
+            previous,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn initialize_mint_registry(ctx: Context<InitializeMintRegistry>) -> Result<()> {
+        require_keys_eq!(ctx.accounts.owner.key(), ctx.accounts.factory.owner, CustomError::Unauthorized);
+
+        let registry = &mut ctx.accounts.mint_registry;
+        registry.mints = Vec::new();
+
+        emit!(MintRegistryInitialized {
+            mint_registry: registry.key(),
+            owner: ctx.accounts.owner.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
         Ok(())
     }
 
-    pub fn create_vault(ctx: Context<CreateVault>, manager: Pubkey) -> Result<()> {
+    pub fn configure_mint(ctx: Context<ConfigureMint>, enabled: bool, deposit_cap: u64) -> Result<()> {
+        require_keys_eq!(ctx.accounts.owner.key(), ctx.accounts.factory.owner, CustomError::Unauthorized);
+        // The native mint stands for the SOL pool, so it cannot back a token pool as well
+        require_keys_neq!(ctx.accounts.mint.key(), token::spl_token::native_mint::ID, CustomError::NativeMintNotAllowed);
+        require_supported_mint(&ctx.accounts.mint.to_account_info())?;
+
+        let mint = &ctx.accounts.mint;
+        let registry = &mut ctx.accounts.mint_registry;
+        match registry.mints.iter_mut().find(|entry| entry.mint == mint.key()) {
+            Some(entry) => {
+                entry.enabled = enabled;
+                entry.deposit_cap = deposit_cap;
+            }
+            None => {
+                require!(registry.mints.len() < MAX_ACCEPTED_MINTS, CustomError::TooManyMints);
+                registry.mints.push(AcceptedMint {
+                    mint: mint.key(),
+                    decimals: mint.decimals,
+                    enabled,
+                    deposit_cap,
+                    total_deposited: 0,
+                });
+            }
+        }
+
+        emit!(MintConfigured {
+            owner: ctx.accounts.owner.key(),
+            mint: mint.key(),
+            enabled,
+            deposit_cap,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn create_vault(ctx: Context<CreateVault>, manager: Pubkey, token_mint: Pubkey) -> Result<()> {
         let factory = &mut ctx.accounts.factory;
         require_keys_eq!(ctx.accounts.owner.key(), factory.owner, CustomError::Unauthorized);
-        require!(!factory.vault_managers.contains(&manager), CustomError::ManagerAlreadyExists);
+        require_keys_neq!(token_mint, token::spl_token::native_mint::ID, CustomError::NativeMintNotAllowed);
+        require!(ctx.accounts.mint_registry.entry(&token_mint)?.enabled, CustomError::MintDisabled);
+
+        // The manager's nonce keeps each of their vaults at its own address
+        let profile = &mut ctx.accounts.manager_profile;
+        let nonce = profile.next_nonce;
+        profile.next_nonce = nonce.checked_add(1).ok_or(CustomError::MathOverflow)?;
+        track_managed_vault(profile, manager, ctx.accounts.vault.key())?;
+
+        // Custody starts rent-exempt so deposits and withdrawals of any size keep it that way
+        let reserve = Rent::get()?.minimum_balance(0).saturating_sub(ctx.accounts.vault_sol_account.lamports());
+        if reserve > 0 {
+            let ix = anchor_lang::solana_program::system_instruction::transfer(
+                &ctx.accounts.owner.key(),
+                &ctx.accounts.vault_sol_account.key(),
+                reserve,
+            );
+            anchor_lang::solana_program::program::invoke(
+                &ix,
+                &[
+                    ctx.accounts.owner.to_account_info(),
+                    ctx.accounts.vault_sol_account.to_account_info(),
+                ],
+            )?;
+        }
 
         let vault = &mut ctx.accounts.vault;
         vault.manager = manager;
+        vault.creator = manager;
+        vault.nonce = nonce;
+        vault.pending_manager = None;
         vault.total_deposit = 0;
         vault.index = factory.vault_count;
+        vault.vault = vault.key();
+        vault.management_fee_bps = 0;
+        vault.performance_fee_bps = 0;
+        vault.deposits_paused = false;
+        vault.withdrawals_paused = false;
+        vault.caps = DepositCaps::default();
+        vault.token_mint = token_mint;
+        vault.allowlist_root = None;
+        vault.lockup = Lockup::default();
+        vault.sol_queue = WithdrawalQueue::default();
+        vault.token_queue = WithdrawalQueue::default();
+        vault.status = VaultStatus::Active;
+
+        let now = Clock::get()?.unix_timestamp;
+        vault.sol_fees = FeeState { last_accrual: now, ..FeeState::default() };
+        vault.token_fees = FeeState { last_accrual: now, ..FeeState::default() };
+
+        // A freshly created page has no factory yet: stamp it and link it from the previous one
+        let page_index = factory.vault_count / VAULTS_PER_PAGE as u32;
+        let page = &mut ctx.accounts.registry_page;
+        if page.factory == Pubkey::default() {
+            page.factory = factory.key();
+            page.index = page_index;
+            page.next_page = None;
+            page.vaults = Vec::new();
+
+            if page_index > 0 {
+                let previous = ctx.accounts.previous_page.as_mut().ok_or(CustomError::MissingPreviousPage)?;
+                previous.next_page = Some(page.key());
+            }
+            factory.page_count += 1;
+        }
+        require!(page.vaults.len() < VAULTS_PER_PAGE, CustomError::RegistryPageFull);
+
+        page.vaults.push(vault.key());
+        factory.vault_count += 1;
+
+        emit!(VaultCreated {
+            factory: factory.key(),
+            vault: vault.key(),
+            manager,
+            nonce,
+            index: vault.index,
+            token_mint,
+            registry_page: page.key(),
+            creator: factory.owner,
+            timestamp: now,
+        });
+        Ok(())
+    }
+
+    pub fn list_vaults(ctx: Context<ListVaults>, _page_index: u32) -> Result<Vec<Pubkey>> {
+        // Walk the registry by calling this for page 0.. until `next_page` is None
+        Ok(ctx.accounts.registry_page.vaults.clone())
+    }
+
+    pub fn list_manager_vaults(ctx: Context<ListManagerVaults>, _manager: Pubkey) -> Result<Vec<Pubkey>> {
+        Ok(ctx.accounts.manager_profile.vaults.clone())
+    }
+
+    pub fn list_positions(ctx: Context<ListPositions>, _user: Pubkey) -> Result<Vec<Pubkey>> {
+        // Each entry is a vault; the position itself is at [b"depositor", vault, user]
+        Ok(ctx.accounts.user_positions.vaults.clone())
+    }
+
+    pub fn position_age(ctx: Context<PositionAge>, _user: Pubkey) -> Result<PositionAges> {
+        let depositor = &ctx.accounts.depositor;
+        let now = Clock::get()?.unix_timestamp;
+
+        Ok(PositionAges {
+            sol: weighted_age(&depositor.sol_lots, now)?,
+            token: weighted_age(&depositor.token_lots, now)?,
+        })
+    }
+
+    pub fn deposit_sol(ctx: Context<DepositSol>, amount: u64, proof: Vec<[u8; 32]>) -> Result<()> {
+        require!(amount > 0, CustomError::InvalidAmount);
+        require!(!ctx.accounts.factory.deposits_paused && !ctx.accounts.vault.deposits_paused, CustomError::Paused);
+        require!(ctx.accounts.vault.status == VaultStatus::Active, CustomError::VaultClosing);
+
+        let vault_key = ctx.accounts.vault.key();
+        open_position(&mut ctx.accounts.depositor, &mut ctx.accounts.user_positions, ctx.accounts.user.key(), vault_key)?;
+        check_allowlist(&ctx.accounts.vault, &mut ctx.accounts.depositor, &proof)?;
+
+        let caps = &ctx.accounts.vault.caps;
+        require!(amount <= remaining_under_cap(caps.sol_total, ctx.accounts.vault.total_deposit), CustomError::DepositCapExceeded);
+
+        // Price the deposit against the pool as it stood before these lamports arrive
+        let total_assets = sol_custody_assets(&ctx.accounts.vault_sol_account)?
+            .saturating_sub(ctx.accounts.vault.pool_queue(&token::spl_token::native_mint::ID).reserved_assets);
+        let total_shares = ctx.accounts.vault.accrue_fees(
+            &token::spl_token::native_mint::ID,
+            total_assets,
+            ctx.accounts.share_mint.supply,
+            Clock::get()?.unix_timestamp,
+        )?;
+        let held = held_assets(ctx.accounts.depositor.sol_amount, ctx.accounts.user_share_account.amount, total_assets, total_shares)?;
+        require!(amount <= remaining_under_cap(ctx.accounts.vault.caps.sol_per_depositor, held), CustomError::DepositCapExceeded);
+
+        let shares = shares_for_assets(amount, total_assets, total_shares)?;
+        require!(shares > 0, CustomError::InvalidAmount);
+
+        let ix = anchor_lang::solana_program::system_instruction::transfer(
+            &ctx.accounts.user.key(),
+            &ctx.accounts.vault_sol_account.key(),
+            amount,
+        );
+        anchor_lang::solana_program::program::invoke(
+            &ix,
+            &[
+                ctx.accounts.user.to_account_info(),
+                ctx.accounts.vault_sol_account.to_account_info(),
+            ],
+        )?;
+
+        let bump = ctx.bumps.vault_authority;
+        let seeds = &[b"vault_authority".as_ref(), vault_key.as_ref(), &[bump]];
+        let signer = &[&seeds[..]];
+
+        let cpi_accounts = MintTo {
+            mint: ctx.accounts.share_mint.to_account_info(),
+            to: ctx.accounts.user_share_account.to_account_info(),
+            authority: ctx.accounts.vault_authority.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), cpi_accounts, signer);
+        token::mint_to(cpi_ctx, shares)?;
+
+        let depositor = &mut ctx.accounts.depositor;
+        let now = Clock::get()?.unix_timestamp;
+        depositor.sol_amount = depositor.sol_amount.checked_add(amount).ok_or(CustomError::MathOverflow)?;
+        depositor.deposit_time = now;
+        sync_lots(&mut depositor.sol_lots, ctx.accounts.user_share_account.amount, now);
+        add_lot(&mut depositor.sol_lots, shares, now);
+
+        let vault = &mut ctx.accounts.vault;
+        vault.total_deposit = vault.total_deposit.checked_add(amount).ok_or(CustomError::MathOverflow)?;
+
+        emit!(Deposited {
+            vault: vault_key,
+            user: depositor.owner,
+            asset_mint: token::spl_token::native_mint::ID,
+            requested: amount,
+            received: amount,
+            shares,
+            depositor_principal: depositor.sol_amount,
+            total_principal: vault.total_deposit,
+            timestamp: now,
+        });
+        Ok(())
+    }
+
+    pub fn deposit_token(ctx: Context<DepositToken>, amount: u64, proof: Vec<[u8; 32]>) -> Result<()> {
+        require!(amount > 0, CustomError::InvalidAmount);
+        require!(!ctx.accounts.factory.deposits_paused && !ctx.accounts.vault.deposits_paused, CustomError::Paused);
+        require!(ctx.accounts.vault.status == VaultStatus::Active, CustomError::VaultClosing);
+
+        let vault_key = ctx.accounts.vault.key();
+        open_position(&mut ctx.accounts.depositor, &mut ctx.accounts.user_positions, ctx.accounts.user.key(), vault_key)?;
+        check_allowlist(&ctx.accounts.vault, &mut ctx.accounts.depositor, &proof)?;
+
+        require_keys_eq!(ctx.accounts.token_mint.key(), ctx.accounts.vault.token_mint, CustomError::InvalidMint);
+        require_keys_eq!(ctx.accounts.user_token_account.mint, ctx.accounts.token_mint.key(), CustomError::InvalidMint);
+
+        require_supported_mint(&ctx.accounts.token_mint.to_account_info())?;
+
+        let balance_before = ctx.accounts.vault_token_account.amount;
+        let total_assets = balance_before
+            .saturating_sub(ctx.accounts.vault.pool_queue(&ctx.accounts.token_mint.key()).reserved_assets);
+        let total_shares = ctx.accounts.vault.accrue_fees(
+            &ctx.accounts.token_mint.key(),
+            total_assets,
+            ctx.accounts.share_mint.supply,
+            Clock::get()?.unix_timestamp,
+        )?;
+
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.user_token_account.to_account_info(),
+            mint: ctx.accounts.token_mint.to_account_info(),
+            to: ctx.accounts.vault_token_account.to_account_info(),
+            authority: ctx.accounts.user.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(ctx.accounts.asset_token_program.to_account_info(), cpi_accounts);
+        token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.token_mint.decimals)?;
+
+        // Shares, caps and principal only count what actually landed in the vault
+        ctx.accounts.vault_token_account.reload()?;
+        let received = ctx.accounts.vault_token_account.amount
+            .checked_sub(balance_before)
+            .ok_or(CustomError::MathOverflow)?;
+
+        let entry = ctx.accounts.mint_registry.entry_mut(&ctx.accounts.token_mint.key())?;
+        require!(entry.enabled, CustomError::MintDisabled);
+        require!(entry.decimals == ctx.accounts.token_mint.decimals, CustomError::InvalidMint);
+        require!(received <= remaining_under_cap(entry.deposit_cap, entry.total_deposited), CustomError::DepositCapExceeded);
+        entry.total_deposited = entry.total_deposited.checked_add(received).ok_or(CustomError::MathOverflow)?;
+
+        let caps = &ctx.accounts.vault.caps;
+        require!(received <= remaining_under_cap(caps.token_total, ctx.accounts.vault.total_token_deposit), CustomError::DepositCapExceeded);
+        let held = held_assets(ctx.accounts.depositor.token_amount, ctx.accounts.user_share_account.amount, total_assets, total_shares)?;
+        require!(received <= remaining_under_cap(caps.token_per_depositor, held), CustomError::DepositCapExceeded);
+
+        let shares = shares_for_assets(received, total_assets, total_shares)?;
+        require!(shares > 0, CustomError::InvalidAmount);
+
+        let bump = ctx.bumps.vault_authority;
+        let seeds = &[b"vault_authority".as_ref(), vault_key.as_ref(), &[bump]];
+        let signer = &[&seeds[..]];
+
+        let cpi_accounts = MintTo {
+            mint: ctx.accounts.share_mint.to_account_info(),
+            to: ctx.accounts.user_share_account.to_account_info(),
+            authority: ctx.accounts.vault_authority.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), cpi_accounts, signer);
+        token::mint_to(cpi_ctx, shares)?;
+
+        let depositor = &mut ctx.accounts.depositor;
+        let now = Clock::get()?.unix_timestamp;
+        depositor.token_amount = depositor.token_amount.checked_add(received).ok_or(CustomError::MathOverflow)?;
+        depositor.deposit_time = now;
+        sync_lots(&mut depositor.token_lots, ctx.accounts.user_share_account.amount, now);
+        add_lot(&mut depositor.token_lots, shares, now);
+
+        let vault = &mut ctx.accounts.vault;
+        vault.total_token_deposit = vault.total_token_deposit.checked_add(received).ok_or(CustomError::MathOverflow)?;
+
+        emit!(Deposited {
+            vault: vault_key,
+            user: depositor.owner,
+            asset_mint: vault.token_mint,
+            requested: amount,
+            received,
+            shares,
+            depositor_principal: depositor.token_amount,
+            total_principal: vault.total_token_deposit,
+            timestamp: now,
+        });
+        Ok(())
+    }
+
+    pub fn withdraw_sol(ctx: Context<WithdrawSol>, shares: u64) -> Result<()> {
+        require!(shares > 0, CustomError::InvalidAmount);
+        require!(!ctx.accounts.factory.withdrawals_paused && !ctx.accounts.vault.withdrawals_paused, CustomError::Paused);
+
+        // Shares are transferable, so a holder without a position yet gets one here
+        let vault_key = ctx.accounts.vault.key();
+        open_position(&mut ctx.accounts.depositor, &mut ctx.accounts.user_positions, ctx.accounts.user.key(), vault_key)?;
+        require!(ctx.accounts.user_share_account.amount >= shares, CustomError::InsufficientBalance);
+
+        let total_assets = sol_custody_assets(&ctx.accounts.vault_sol_account)?
+            .saturating_sub(ctx.accounts.vault.pool_queue(&token::spl_token::native_mint::ID).reserved_assets);
+        let total_shares = ctx.accounts.vault.accrue_fees(
+            &token::spl_token::native_mint::ID,
+            total_assets,
+            ctx.accounts.share_mint.supply,
+            Clock::get()?.unix_timestamp,
+        )?;
+        let now = Clock::get()?.unix_timestamp;
+        sync_lots(&mut ctx.accounts.depositor.sol_lots, ctx.accounts.user_share_account.amount, now);
+        let locked = consume_lots(&mut ctx.accounts.depositor.sol_lots, shares, ctx.accounts.vault.lockup.lock_period, now);
+        let penalty_shares = ctx.accounts.vault.take_early_exit_penalty(&token::spl_token::native_mint::ID, locked)?;
+        let amount = assets_for_shares(shares - penalty_shares, total_assets, total_shares)?;
+        require!(amount > 0, CustomError::NothingToWithdraw);
+
+        let cpi_accounts = Burn {
+            mint: ctx.accounts.share_mint.to_account_info(),
+            from: ctx.accounts.user_share_account.to_account_info(),
+            authority: ctx.accounts.user.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+        token::burn(cpi_ctx, shares)?;
+
+        let bump = ctx.bumps.vault_sol_account;
+        let seeds = &[b"vault_sol".as_ref(), vault_key.as_ref(), &[bump]];
+        let signer = &[&seeds[..]];
+
+        let ix = anchor_lang::solana_program::system_instruction::transfer(
+            &ctx.accounts.vault_sol_account.key(),
+            &ctx.accounts.user.key(),
+            amount,
+        );
+        anchor_lang::solana_program::program::invoke_signed(
+            &ix,
+            &[
+                ctx.accounts.vault_sol_account.to_account_info(),
+                ctx.accounts.user.to_account_info(),
+            ],
+            signer,
+        )?;
+
+        // Payouts include yield, so principal counters bottom out at zero
+        let depositor = &mut ctx.accounts.depositor;
+        depositor.sol_amount = depositor.sol_amount.saturating_sub(amount);
+        let vault = &mut ctx.accounts.vault;
+        vault.total_deposit = vault.total_deposit.saturating_sub(amount);
+
+        emit!(Withdrawn {
+            vault: vault_key,
+            user: depositor.owner,
+            asset_mint: token::spl_token::native_mint::ID,
+            shares,
+            penalty_shares,
+            amount,
+            depositor_principal: depositor.sol_amount,
+            total_principal: vault.total_deposit,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn withdraw_token(ctx: Context<WithdrawToken>, shares: u64) -> Result<()> {
+        require!(shares > 0, CustomError::InvalidAmount);
+        require!(!ctx.accounts.factory.withdrawals_paused && !ctx.accounts.vault.withdrawals_paused, CustomError::Paused);
+
+        let vault_key = ctx.accounts.vault.key();
+        open_position(&mut ctx.accounts.depositor, &mut ctx.accounts.user_positions, ctx.accounts.user.key(), vault_key)?;
+        require!(ctx.accounts.user_share_account.amount >= shares, CustomError::InsufficientBalance);
+
+        // Disabled mints still pay out so nobody is stuck in a delisted token
+        require_keys_eq!(ctx.accounts.token_mint.key(), ctx.accounts.vault.token_mint, CustomError::InvalidMint);
+        require_keys_eq!(ctx.accounts.user_token_account.mint, ctx.accounts.token_mint.key(), CustomError::InvalidMint);
+        require_keys_eq!(ctx.accounts.vault_token_account.mint, ctx.accounts.token_mint.key(), CustomError::InvalidMint);
+
+        let total_assets = ctx.accounts.vault_token_account.amount
+            .saturating_sub(ctx.accounts.vault.pool_queue(&ctx.accounts.token_mint.key()).reserved_assets);
+        let total_shares = ctx.accounts.vault.accrue_fees(
+            &ctx.accounts.token_mint.key(),
+            total_assets,
+            ctx.accounts.share_mint.supply,
+            Clock::get()?.unix_timestamp,
+        )?;
+        let now = Clock::get()?.unix_timestamp;
+        sync_lots(&mut ctx.accounts.depositor.token_lots, ctx.accounts.user_share_account.amount, now);
+        let locked = consume_lots(&mut ctx.accounts.depositor.token_lots, shares, ctx.accounts.vault.lockup.lock_period, now);
+        let penalty_shares = ctx.accounts.vault.take_early_exit_penalty(&ctx.accounts.token_mint.key(), locked)?;
+        let amount = assets_for_shares(shares - penalty_shares, total_assets, total_shares)?;
+        require!(amount > 0, CustomError::NothingToWithdraw);
+
+        let cpi_accounts = Burn {
+            mint: ctx.accounts.share_mint.to_account_info(),
+            from: ctx.accounts.user_share_account.to_account_info(),
+            authority: ctx.accounts.user.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+        token::burn(cpi_ctx, shares)?;
+
+        let bump = ctx.bumps.vault_authority;
+        let seeds = &[b"vault_authority".as_ref(), vault_key.as_ref(), &[bump]];
+        let signer = &[&seeds[..]];
+
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.vault_token_account.to_account_info(),
+            mint: ctx.accounts.token_mint.to_account_info(),
+            to: ctx.accounts.user_token_account.to_account_info(),
+            authority: ctx.accounts.vault_authority.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(ctx.accounts.asset_token_program.to_account_info(), cpi_accounts, signer);
+        token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.token_mint.decimals)?;
+
+        let entry = ctx.accounts.mint_registry.entry_mut(&ctx.accounts.token_mint.key())?;
+        entry.total_deposited = entry.total_deposited.saturating_sub(amount);
+
+        let depositor = &mut ctx.accounts.depositor;
+        depositor.token_amount = depositor.token_amount.saturating_sub(amount);
+        let vault = &mut ctx.accounts.vault;
+        vault.total_token_deposit = vault.total_token_deposit.saturating_sub(amount);
+
+        emit!(Withdrawn {
+            vault: vault_key,
+            user: depositor.owner,
+            asset_mint: vault.token_mint,
+            shares,
+            penalty_shares,
+            amount,
+            depositor_principal: depositor.token_amount,
+            total_principal: vault.total_token_deposit,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn convert_to_shares(ctx: Context<ConvertShares>, assets: u64) -> Result<u64> {
+        let (total_assets, total_shares) = preview_pool(&ctx.accounts.vault, ctx.accounts.asset_mint.key(), ctx.accounts.share_mint.supply, &ctx.accounts.custody)?;
+        shares_for_assets(assets, total_assets, total_shares)
+    }
+
+    pub fn convert_to_assets(ctx: Context<ConvertShares>, shares: u64) -> Result<u64> {
+        let (total_assets, total_shares) = preview_pool(&ctx.accounts.vault, ctx.accounts.asset_mint.key(), ctx.accounts.share_mint.supply, &ctx.accounts.custody)?;
+        assets_for_shares(shares, total_assets, total_shares)
+    }
+
+    pub fn preview_deposit(ctx: Context<ConvertShares>, assets: u64) -> Result<u64> {
+        // Transfer-fee mints deliver less than `assets` to the vault
+        let fee = transfer_fee(&ctx.accounts.asset_mint.to_account_info(), assets)?;
+        convert_to_shares(ctx, assets.checked_sub(fee).ok_or(CustomError::MathOverflow)?)
+    }
+
+    pub fn preview_redeem(ctx: Context<ConvertShares>, shares: u64) -> Result<u64> {
+        convert_to_assets(ctx, shares)
+    }
+
+    // Same two-step handover for a vault's manager; the vault keeps its address throughout
+    pub fn propose_manager(ctx: Context<UpdateVaultManager>, new_manager: Pubkey) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        require_keys_eq!(ctx.accounts.authority.key(), vault.manager, CustomError::Unauthorized);
+        require_keys_neq!(new_manager, Pubkey::default(), CustomError::InvalidAuthority);
+        require_keys_neq!(new_manager, vault.manager, CustomError::InvalidAuthority);
+
+        vault.pending_manager = Some(new_manager);
+
+        emit!(AuthorityProposed {
+            account: vault.key(),
+            authority: vault.manager,
+            pending: new_manager,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn accept_manager(ctx: Context<AcceptManager>) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        let pending = vault.pending_manager.ok_or(CustomError::NoPendingAuthority)?;
+        require_keys_eq!(ctx.accounts.authority.key(), pending, CustomError::Unauthorized);
+
+        untrack_managed_vault(&mut ctx.accounts.previous_profile, vault.key());
+        track_managed_vault(&mut ctx.accounts.manager_profile, pending, vault.key())?;
+
+        let previous = vault.manager;
+        vault.manager = pending;
+        vault.pending_manager = None;
+
+        emit!(AuthorityTransferred {
+            account: vault.key(),
+            previous,
+            authority: vault.manager,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn cancel_manager_transfer(ctx: Context<UpdateVaultManager>) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        require_keys_eq!(ctx.accounts.authority.key(), vault.manager, CustomError::Unauthorized);
+        let pending = vault.pending_manager.ok_or(CustomError::NoPendingAuthority)?;
+
+        vault.pending_manager = None;
+
+        emit!(AuthorityTransferCancelled {
+            account: vault.key(),
+            authority: vault.manager,
+            pending,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // Manager-only instructions stop working; the factory owner keeps its pause and cap powers and
+    // can still hand the vault to a new manager with `rotate_manager`, e.g. to close it
+    pub fn renounce_manager(ctx: Context<RenounceManager>) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        require_keys_eq!(ctx.accounts.authority.key(), vault.manager, CustomError::Unauthorized);
+
+        untrack_managed_vault(&mut ctx.accounts.manager_profile, vault.key());
+
+        let previous = vault.manager;
+        vault.manager = Pubkey::default();
+        vault.pending_manager = None;
+
+        emit!(AuthorityRenounced {
+            account: vault.key(),
+            previous,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // Immediate replacement without the new manager's signature: the outgoing manager can hand over
+    // and the factory owner can override a lost or unresponsive manager. The vault and its custody
+    // keep their addresses, so no funds move.
+    pub fn rotate_manager(ctx: Context<RotateManager>, new_manager: Pubkey) -> Result<()> {
+        let authority = ctx.accounts.authority.key();
+        let vault = &mut ctx.accounts.vault;
+        require!(
+            authority == vault.manager || authority == ctx.accounts.factory.owner,
+            CustomError::Unauthorized
+        );
+        require_keys_neq!(new_manager, Pubkey::default(), CustomError::InvalidAuthority);
+        require_keys_neq!(new_manager, vault.manager, CustomError::InvalidAuthority);
+
+        // A renounced vault has no previous profile; the factory owner installs a manager to close it
+        if vault.manager != Pubkey::default() {
+            let previous_profile = ctx.accounts.previous_profile.as_mut().ok_or(CustomError::ManagerProfileRequired)?;
+            untrack_managed_vault(previous_profile, vault.key());
+        }
+        track_managed_vault(&mut ctx.accounts.manager_profile, new_manager, vault.key())?;
+
+        let previous = vault.manager;
+        vault.manager = new_manager;
+        vault.pending_manager = None;
+
+        emit!(ManagerRotated {
+            vault: vault.key(),
+            authority,
+            previous,
+            manager: new_manager,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // First step of decommissioning: deposits stop for good while withdrawals, requests and claims
+    // keep working, and lock penalties no longer apply
+    pub fn begin_close(ctx: Context<BeginClose>) -> Result<()> {
+        let authority = ctx.accounts.authority.key();
+        let vault = &mut ctx.accounts.vault;
+        require!(
+            authority == vault.manager || authority == ctx.accounts.factory.owner,
+            CustomError::Unauthorized
+        );
+        require!(vault.status == VaultStatus::Active, CustomError::VaultClosing);
+
+        vault.status = VaultStatus::Closing;
+
+        emit!(VaultClosing {
+            vault: vault.key(),
+            authority,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // Once every share is burned and every settled withdrawal claimed, whatever is left in custody
+    // (rounding dust, donations, unminted fee shares) belongs to the manager. Custody accounts and the
+    // vault are closed to them and the vault leaves the registry. Share mints cannot be closed under
+    // the legacy Token program and stay behind with zero supply.
+    pub fn close_vault(ctx: Context<CloseVault>) -> Result<()> {
+        let vault_key = ctx.accounts.vault.key();
+        let vault = &ctx.accounts.vault;
+        require_keys_eq!(ctx.accounts.manager.key(), vault.manager, CustomError::Unauthorized);
+        require!(vault.status == VaultStatus::Closing, CustomError::VaultNotClosing);
+        require!(
+            share_supply(&ctx.accounts.sol_share_mint)? == 0 && share_supply(&ctx.accounts.token_share_mint)? == 0,
+            CustomError::OutstandingLiabilities
+        );
+        require!(
+            vault.sol_queue.reserved_assets == 0 && vault.token_queue.reserved_assets == 0,
+            CustomError::OutstandingLiabilities
+        );
+
+        let sol_swept = ctx.accounts.vault_sol_account.lamports();
+        if sol_swept > 0 {
+            let bump = ctx.bumps.vault_sol_account;
+            let seeds = &[b"vault_sol".as_ref(), vault_key.as_ref(), &[bump]];
+            let signer = &[&seeds[..]];
+
+            let ix = anchor_lang::solana_program::system_instruction::transfer(
+                &ctx.accounts.vault_sol_account.key(),
+                &ctx.accounts.manager.key(),
+                sol_swept,
+            );
+            anchor_lang::solana_program::program::invoke_signed(
+                &ix,
+                &[
+                    ctx.accounts.vault_sol_account.to_account_info(),
+                    ctx.accounts.manager.to_account_info(),
+                ],
+                signer,
+            )?;
+        }
+
+        let bump = ctx.bumps.vault_authority;
+        let seeds = &[b"vault_authority".as_ref(), vault_key.as_ref(), &[bump]];
+        let signer = &[&seeds[..]];
+
+        // Token-2022 fees withheld in custody have to be harvested to the mint before it can close
+        let mut token_swept = 0;
+        let custody = ctx.accounts.vault_token_account.to_account_info();
+        if !custody.data_is_empty() {
+            token_swept = token_interface::TokenAccount::try_deserialize(&mut &custody.data.borrow()[..])?.amount;
+            if token_swept > 0 {
+                let destination = ctx.accounts.manager_token_account.as_ref().ok_or(CustomError::TokenAccountRequired)?;
+                let cpi_accounts = TransferChecked {
+                    from: custody.clone(),
+                    mint: ctx.accounts.token_mint.to_account_info(),
+                    to: destination.to_account_info(),
+                    authority: ctx.accounts.vault_authority.to_account_info(),
+                };
+                let cpi_ctx = CpiContext::new_with_signer(ctx.accounts.asset_token_program.to_account_info(), cpi_accounts, signer);
+                token_interface::transfer_checked(cpi_ctx, token_swept, ctx.accounts.token_mint.decimals)?;
+            }
+
+            let cpi_accounts = CloseAccount {
+                account: custody,
+                destination: ctx.accounts.manager.to_account_info(),
+                authority: ctx.accounts.vault_authority.to_account_info(),
+            };
+            let cpi_ctx = CpiContext::new_with_signer(ctx.accounts.asset_token_program.to_account_info(), cpi_accounts, signer);
+            token_interface::close_account(cpi_ctx)?;
+        }
+
+        // Escrows are empty once no shares exist
+        for escrow in [&ctx.accounts.sol_escrow, &ctx.accounts.token_escrow] {
+            if escrow.data_is_empty() {
+                continue;
+            }
+            let cpi_accounts = token::CloseAccount {
+                account: escrow.to_account_info(),
+                destination: ctx.accounts.manager.to_account_info(),
+                authority: ctx.accounts.vault_authority.to_account_info(),
+            };
+            let cpi_ctx = CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), cpi_accounts, signer);
+            token::close_account(cpi_ctx)?;
+        }
+
+        // The vault keeps its index; the page simply holds one entry fewer
+        ctx.accounts.registry_page.vaults.retain(|listed| *listed != vault_key);
+        untrack_managed_vault(&mut ctx.accounts.manager_profile, vault_key);
+
+        emit!(VaultClosed {
+            vault: vault_key,
+            manager: ctx.accounts.manager.key(),
+            sol_swept,
+            token_swept,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // Returns a position's rent once nothing depends on it. While the vault is open its locks must
+    // have run out, since closing drops the lots; after the vault is closed anything goes.
+    pub fn close_position(ctx: Context<ClosePosition>) -> Result<()> {
+        let vault_key = ctx.accounts.vault.key();
+        let user = ctx.accounts.user.key();
+        let depositor = &ctx.accounts.depositor;
+        require_keys_eq!(depositor.owner, user, CustomError::Unauthorized);
+
+        let vault_info = ctx.accounts.vault.to_account_info();
+        if *vault_info.owner == crate::ID && !vault_info.data_is_empty() {
+            let vault = Vault::try_deserialize(&mut &vault_info.data.borrow()[..])?;
+            require_keys_eq!(ctx.accounts.token_mint.key(), vault.token_mint, CustomError::InvalidMint);
+            // Settled or queued requests are claimed and cancelled through the position
+            require!(
+                ctx.accounts.sol_request.data_is_empty() && ctx.accounts.token_request.data_is_empty(),
+                CustomError::WithdrawalPending
+            );
+
+            if vault.status == VaultStatus::Active {
+                let now = Clock::get()?.unix_timestamp;
+                let locked = depositor.sol_lots.iter().chain(depositor.token_lots.iter())
+                    .any(|lot| now < lot.deposit_time.saturating_add(vault.lockup.lock_period));
+                require!(!locked, CustomError::Locked);
+            }
+        }
+
+        ctx.accounts.user_positions.vaults.retain(|position| *position != vault_key);
+
+        emit!(PositionClosed {
+            vault: vault_key,
+            user,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // Starts the lock on shares received from elsewhere without redeeming any, so a holder can wait
+    // it out where the vault refuses early exits
+    pub fn sync_position(ctx: Context<SyncPosition>) -> Result<()> {
+        let vault_key = ctx.accounts.vault.key();
+        open_position(&mut ctx.accounts.depositor, &mut ctx.accounts.user_positions, ctx.accounts.user.key(), vault_key)?;
+
+        let asset_mint = ctx.accounts.asset_mint.key();
+        let depositor = &mut ctx.accounts.depositor;
+        let lots = if asset_mint == token::spl_token::native_mint::ID { &mut depositor.sol_lots } else { &mut depositor.token_lots };
+        let now = Clock::get()?.unix_timestamp;
+        sync_lots(lots, ctx.accounts.user_share_account.amount, now);
+
+        emit!(PositionSynced {
+            vault: vault_key,
+            user: ctx.accounts.user.key(),
+            asset_mint,
+            shares: ctx.accounts.user_share_account.amount,
+            timestamp: now,
+        });
+        Ok(())
+    }
+
+    pub fn set_lockup(ctx: Context<SetLockup>, lockup: Lockup) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        require_keys_eq!(ctx.accounts.manager.key(), vault.manager, CustomError::Unauthorized);
+        require!(lockup.lock_period >= 0, CustomError::InvalidLockup);
+        require!(lockup.early_exit_penalty_bps as u128 <= BPS_DENOMINATOR, CustomError::InvalidLockup);
+
+        vault.lockup = lockup;
+
+        emit!(LockupUpdated {
+            vault: vault.key(),
+            manager: vault.manager,
+            lockup,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn set_allowlist_root(ctx: Context<SetAllowlistRoot>, root: Option<[u8; 32]>) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        require_keys_eq!(ctx.accounts.manager.key(), vault.manager, CustomError::Unauthorized);
+
+        vault.allowlist_root = root;
+
+        emit!(AllowlistRootUpdated {
+            vault: vault.key(),
+            manager: vault.manager,
+            root,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn set_deposit_caps(ctx: Context<SetDepositCaps>, caps: DepositCaps) -> Result<()> {
+        let authority = ctx.accounts.authority.key();
+        let vault = &mut ctx.accounts.vault;
+        require!(
+            authority == vault.manager || authority == ctx.accounts.factory.owner,
+            CustomError::Unauthorized
+        );
+
+        vault.caps = caps;
+
+        emit!(DepositCapsUpdated {
+            vault: vault.key(),
+            authority,
+            caps,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn deposit_capacity(ctx: Context<GetDepositCapacity>, _user: Pubkey) -> Result<DepositCapacity> {
+        let vault = &ctx.accounts.vault;
+        let mut capacity = DepositCapacity {
+            sol: remaining_under_cap(vault.caps.sol_total, vault.total_deposit),
+            token: remaining_under_cap(vault.caps.token_total, vault.total_token_deposit),
+        };
+
+        // Without a position yet only shares the wallet already holds count against its allowance
+        let (sol_amount, token_amount) = match &ctx.accounts.depositor {
+            Some(depositor) => (depositor.sol_amount, depositor.token_amount),
+            None => (0, 0),
+        };
+        let sol_held = wallet_held_assets(
+            vault,
+            token::spl_token::native_mint::ID,
+            sol_amount,
+            &ctx.accounts.sol_share_mint,
+            &ctx.accounts.vault_sol_account,
+            &ctx.accounts.sol_share_account,
+        )?;
+        let token_held = wallet_held_assets(
+            vault,
+            vault.token_mint,
+            token_amount,
+            &ctx.accounts.token_share_mint,
+            &ctx.accounts.vault_token_account,
+            &ctx.accounts.token_share_account,
+        )?;
+        capacity.sol = capacity.sol.min(remaining_under_cap(vault.caps.sol_per_depositor, sol_held));
+        capacity.token = capacity.token.min(remaining_under_cap(vault.caps.token_per_depositor, token_held));
+
+        let entry = ctx.accounts.mint_registry.entry(&vault.token_mint)?;
+        capacity.token = if entry.enabled {
+            capacity.token.min(remaining_under_cap(entry.deposit_cap, entry.total_deposited))
+        } else {
+            0
+        };
+        Ok(capacity)
+    }
+
+    pub fn set_vault_fees(ctx: Context<SetVaultFees>, management_fee_bps: u16, performance_fee_bps: u16) -> Result<()> {
+        let factory = &ctx.accounts.factory;
+        let vault = &mut ctx.accounts.vault;
+        require_keys_eq!(ctx.accounts.manager.key(), vault.manager, CustomError::Unauthorized);
+        require!(management_fee_bps <= factory.max_management_fee_bps, CustomError::FeeTooHigh);
+        require!(performance_fee_bps <= factory.max_performance_fee_bps, CustomError::FeeTooHigh);
+
+        // Both pools are billed at the old rates up to now; the new ones only apply from here on
+        let now = Clock::get()?.unix_timestamp;
+        let sol_assets = pool_assets(vault, token::spl_token::native_mint::ID, &ctx.accounts.vault_sol_account)?;
+        vault.accrue_fees(&token::spl_token::native_mint::ID, sol_assets, share_supply(&ctx.accounts.sol_share_mint)?, now)?;
+        let token_mint = vault.token_mint;
+        // Token custody is created with the pool's first deposit
+        let token_assets = if ctx.accounts.vault_token_account.data_is_empty() {
+            0
+        } else {
+            pool_assets(vault, token_mint, &ctx.accounts.vault_token_account)?
+        };
+        vault.accrue_fees(&token_mint, token_assets, share_supply(&ctx.accounts.token_share_mint)?, now)?;
+
+        vault.management_fee_bps = management_fee_bps;
+        vault.performance_fee_bps = performance_fee_bps;
+
+        emit!(VaultFeesUpdated {
+            vault: vault.key(),
+            manager: vault.manager,
+            management_fee_bps,
+            performance_fee_bps,
+            timestamp: now,
+        });
+        Ok(())
+    }
+
+    pub fn accrue_fees(ctx: Context<AccrueFees>) -> Result<()> {
+        let asset_mint = ctx.accounts.asset_mint.key();
+        let total_assets = pool_assets(&ctx.accounts.vault, asset_mint, &ctx.accounts.custody)?;
+        ctx.accounts.vault.accrue_fees(&asset_mint, total_assets, ctx.accounts.share_mint.supply, Clock::get()?.unix_timestamp)?;
+        Ok(())
+    }
+
+    pub fn claim_fees(ctx: Context<ClaimFees>) -> Result<()> {
+        let vault_key = ctx.accounts.vault.key();
+        let asset_mint = ctx.accounts.asset_mint.key();
+        require_keys_eq!(ctx.accounts.manager.key(), ctx.accounts.vault.manager, CustomError::Unauthorized);
+
+        let total_assets = pool_assets(&ctx.accounts.vault, asset_mint, &ctx.accounts.custody)?;
+        ctx.accounts.vault.accrue_fees(&asset_mint, total_assets, ctx.accounts.share_mint.supply, Clock::get()?.unix_timestamp)?;
+
+        let fees = ctx.accounts.vault.pool_fees_mut(&asset_mint);
+        let shares = fees.pending_shares;
+        require!(shares > 0, CustomError::NothingToWithdraw);
+        fees.pending_shares = 0;
+
+        let bump = ctx.bumps.vault_authority;
+        let seeds = &[b"vault_authority".as_ref(), vault_key.as_ref(), &[bump]];
+        let signer = &[&seeds[..]];
+
+        let cpi_accounts = MintTo {
+            mint: ctx.accounts.share_mint.to_account_info(),
+            to: ctx.accounts.manager_share_account.to_account_info(),
+            authority: ctx.accounts.vault_authority.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), cpi_accounts, signer);
+        token::mint_to(cpi_ctx, shares)?;
+
+        emit!(FeesClaimed {
+            vault: vault_key,
+            manager: ctx.accounts.manager.key(),
+            asset_mint,
+            shares,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // Queues shares for the pool's open withdrawal epoch. Lock penalties are taken now; the rest of
+    // the shares wait in escrow until the epoch is settled at a single price.
+    pub fn request_withdraw(ctx: Context<RequestWithdraw>, shares: u64) -> Result<()> {
+        require!(shares > 0, CustomError::InvalidAmount);
+        require!(!ctx.accounts.factory.withdrawals_paused && !ctx.accounts.vault.withdrawals_paused, CustomError::Paused);
+
+        let vault_key = ctx.accounts.vault.key();
+        let asset_mint = ctx.accounts.asset_mint.key();
+        open_position(&mut ctx.accounts.depositor, &mut ctx.accounts.user_positions, ctx.accounts.user.key(), vault_key)?;
+        require!(ctx.accounts.user_share_account.amount >= shares, CustomError::InsufficientBalance);
+
+        let epoch = ctx.accounts.vault.pool_queue(&asset_mint).epoch;
+        let request = &mut ctx.accounts.withdrawal_request;
+        if request.shares > 0 {
+            require!(request.epoch == epoch, CustomError::WithdrawalPending);
+        }
+        request.owner = ctx.accounts.user.key();
+        request.vault = vault_key;
+        request.asset_mint = asset_mint;
+        request.epoch = epoch;
+
+        let total_assets = pool_assets(&ctx.accounts.vault, asset_mint, &ctx.accounts.custody)?;
+        ctx.accounts.vault.accrue_fees(&asset_mint, total_assets, ctx.accounts.share_mint.supply, Clock::get()?.unix_timestamp)?;
+
+        let depositor = &mut ctx.accounts.depositor;
+        let lots = if asset_mint == token::spl_token::native_mint::ID { &mut depositor.sol_lots } else { &mut depositor.token_lots };
+        let now = Clock::get()?.unix_timestamp;
+        sync_lots(lots, ctx.accounts.user_share_account.amount, now);
+        let mut taken = take_lots(lots, shares);
+        let locked = locked_shares(&taken, ctx.accounts.vault.lockup.lock_period, now);
+        let penalty_shares = ctx.accounts.vault.take_early_exit_penalty(&asset_mint, locked)?;
+        let queued = shares - penalty_shares;
+        require!(queued > 0, CustomError::NothingToWithdraw);
+
+        // The escrow remembers when its shares were deposited
+        trim_newest(&mut taken, penalty_shares);
+        restore_lots(&mut ctx.accounts.withdrawal_request.lots, &taken);
+
+        if penalty_shares > 0 {
+            let cpi_accounts = Burn {
+                mint: ctx.accounts.share_mint.to_account_info(),
+                from: ctx.accounts.user_share_account.to_account_info(),
+                authority: ctx.accounts.user.to_account_info(),
+            };
+            let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+            token::burn(cpi_ctx, penalty_shares)?;
+        }
+
+        let cpi_accounts = token::Transfer {
+            from: ctx.accounts.user_share_account.to_account_info(),
+            to: ctx.accounts.escrow_share_account.to_account_info(),
+            authority: ctx.accounts.user.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+        token::transfer(cpi_ctx, queued)?;
+
+        let request = &mut ctx.accounts.withdrawal_request;
+        request.shares = request.shares.checked_add(queued).ok_or(CustomError::MathOverflow)?;
+
+        let withdrawal_epoch = &mut ctx.accounts.withdrawal_epoch;
+        withdrawal_epoch.vault = vault_key;
+        withdrawal_epoch.asset_mint = asset_mint;
+        withdrawal_epoch.epoch = epoch;
+        withdrawal_epoch.shares = withdrawal_epoch.shares.checked_add(queued).ok_or(CustomError::MathOverflow)?;
+
+        emit!(WithdrawalRequested {
+            vault: vault_key,
+            user: request.owner,
+            asset_mint,
+            epoch,
+            shares,
+            penalty_shares,
+            request_shares: request.shares,
+            epoch_shares: withdrawal_epoch.shares,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // Returns escrowed shares from an epoch that has not been settled yet; lock penalties are not refunded
+    pub fn cancel_withdrawal(ctx: Context<CancelWithdrawal>) -> Result<()> {
+        require!(!ctx.accounts.factory.withdrawals_paused && !ctx.accounts.vault.withdrawals_paused, CustomError::Paused);
+        require!(!ctx.accounts.withdrawal_epoch.settled, CustomError::WithdrawalSettled);
+
+        let vault_key = ctx.accounts.vault.key();
+        let shares = ctx.accounts.withdrawal_request.shares;
+
+        let bump = ctx.bumps.vault_authority;
+        let seeds = &[b"vault_authority".as_ref(), vault_key.as_ref(), &[bump]];
+        let signer = &[&seeds[..]];
+
+        let cpi_accounts = token::Transfer {
+            from: ctx.accounts.escrow_share_account.to_account_info(),
+            to: ctx.accounts.user_share_account.to_account_info(),
+            authority: ctx.accounts.vault_authority.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), cpi_accounts, signer);
+        token::transfer(cpi_ctx, shares)?;
+
+        let withdrawal_epoch = &mut ctx.accounts.withdrawal_epoch;
+        withdrawal_epoch.shares = withdrawal_epoch.shares.saturating_sub(shares);
+
+        // Returned shares keep their original deposit times, so cancelling never restarts a lock
+        let depositor = &mut ctx.accounts.depositor;
+        let lots = if ctx.accounts.withdrawal_request.asset_mint == token::spl_token::native_mint::ID {
+            &mut depositor.sol_lots
+        } else {
+            &mut depositor.token_lots
+        };
+        let now = Clock::get()?.unix_timestamp;
+        sync_lots(lots, ctx.accounts.user_share_account.amount, now);
+        restore_lots(lots, &ctx.accounts.withdrawal_request.lots);
+
+        emit!(WithdrawalCancelled {
+            vault: vault_key,
+            user: ctx.accounts.user.key(),
+            asset_mint: ctx.accounts.withdrawal_request.asset_mint,
+            epoch: withdrawal_epoch.epoch,
+            shares,
+            epoch_shares: withdrawal_epoch.shares,
+            timestamp: now,
+        });
+        Ok(())
+    }
+
+    // Anyone may settle once the pool holds enough to pay the whole epoch. The escrowed shares are
+    // burned at today's price and the assets are set aside until each request is claimed.
+    pub fn settle_withdrawals(ctx: Context<SettleWithdrawals>) -> Result<()> {
+        require!(!ctx.accounts.factory.withdrawals_paused && !ctx.accounts.vault.withdrawals_paused, CustomError::Paused);
+        let vault_key = ctx.accounts.vault.key();
+        let asset_mint = ctx.accounts.asset_mint.key();
+        let shares = ctx.accounts.withdrawal_epoch.shares;
+        require!(shares > 0, CustomError::NothingToWithdraw);
+
+        let total_assets = pool_assets(&ctx.accounts.vault, asset_mint, &ctx.accounts.custody)?;
+        let total_shares = ctx.accounts.vault.accrue_fees(
+            &asset_mint,
+            total_assets,
+            ctx.accounts.share_mint.supply,
+            Clock::get()?.unix_timestamp,
+        )?;
+        let assets = assets_for_shares(shares, total_assets, total_shares)?;
+        require!(assets <= total_assets, CustomError::InsufficientBalance);
+
+        let bump = ctx.bumps.vault_authority;
+        let seeds = &[b"vault_authority".as_ref(), vault_key.as_ref(), &[bump]];
+        let signer = &[&seeds[..]];
+
+        let cpi_accounts = Burn {
+            mint: ctx.accounts.share_mint.to_account_info(),
+            from: ctx.accounts.escrow_share_account.to_account_info(),
+            authority: ctx.accounts.vault_authority.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), cpi_accounts, signer);
+        token::burn(cpi_ctx, shares)?;
+
+        let withdrawal_epoch = &mut ctx.accounts.withdrawal_epoch;
+        withdrawal_epoch.assets = assets;
+        withdrawal_epoch.settled = true;
+
+        let vault = &mut ctx.accounts.vault;
+        let queue = vault.pool_queue_mut(&asset_mint);
+        queue.epoch = queue.epoch.checked_add(1).ok_or(CustomError::MathOverflow)?;
+        queue.reserved_assets = queue.reserved_assets.checked_add(assets).ok_or(CustomError::MathOverflow)?;
+
+        // Payouts include yield, so principal counters bottom out at zero
+        if asset_mint == token::spl_token::native_mint::ID {
+            vault.total_deposit = vault.total_deposit.saturating_sub(assets);
+        } else {
+            vault.total_token_deposit = vault.total_token_deposit.saturating_sub(assets);
+            let entry = ctx.accounts.mint_registry.entry_mut(&asset_mint)?;
+            entry.total_deposited = entry.total_deposited.saturating_sub(assets);
+        }
+
+        let queue = vault.pool_queue(&asset_mint);
+        emit!(WithdrawalsSettled {
+            vault: vault_key,
+            asset_mint,
+            epoch: withdrawal_epoch.epoch,
+            shares,
+            assets,
+            next_epoch: queue.epoch,
+            reserved_assets: queue.reserved_assets,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn claim_withdrawal_sol(ctx: Context<ClaimWithdrawalSol>) -> Result<()> {
+        require!(!ctx.accounts.factory.withdrawals_paused && !ctx.accounts.vault.withdrawals_paused, CustomError::Paused);
+        let amount = ctx.accounts.withdrawal_epoch.payout(ctx.accounts.withdrawal_request.shares)?;
+        let vault_key = ctx.accounts.vault.key();
+
+        let bump = ctx.bumps.vault_sol_account;
+        let seeds = &[b"vault_sol".as_ref(), vault_key.as_ref(), &[bump]];
+        let signer = &[&seeds[..]];
+
+        let ix = anchor_lang::solana_program::system_instruction::transfer(
+            &ctx.accounts.vault_sol_account.key(),
+            &ctx.accounts.user.key(),
+            amount,
+        );
+        anchor_lang::solana_program::program::invoke_signed(
+            &ix,
+            &[
+                ctx.accounts.vault_sol_account.to_account_info(),
+                ctx.accounts.user.to_account_info(),
+            ],
+            signer,
+        )?;
+
+        let queue = ctx.accounts.vault.pool_queue_mut(&token::spl_token::native_mint::ID);
+        queue.reserved_assets = queue.reserved_assets.saturating_sub(amount);
+        let depositor = &mut ctx.accounts.depositor;
+        depositor.sol_amount = depositor.sol_amount.saturating_sub(amount);
+
+        emit!(WithdrawalClaimed {
+            vault: vault_key,
+            user: depositor.owner,
+            asset_mint: token::spl_token::native_mint::ID,
+            epoch: ctx.accounts.withdrawal_request.epoch,
+            shares: ctx.accounts.withdrawal_request.shares,
+            amount,
+            depositor_principal: depositor.sol_amount,
+            reserved_assets: queue.reserved_assets,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn claim_withdrawal_token(ctx: Context<ClaimWithdrawalToken>) -> Result<()> {
+        require!(!ctx.accounts.factory.withdrawals_paused && !ctx.accounts.vault.withdrawals_paused, CustomError::Paused);
+        let amount = ctx.accounts.withdrawal_epoch.payout(ctx.accounts.withdrawal_request.shares)?;
+        let vault_key = ctx.accounts.vault.key();
+
+        let bump = ctx.bumps.vault_authority;
+        let seeds = &[b"vault_authority".as_ref(), vault_key.as_ref(), &[bump]];
+        let signer = &[&seeds[..]];
+
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.vault_token_account.to_account_info(),
+            mint: ctx.accounts.token_mint.to_account_info(),
+            to: ctx.accounts.user_token_account.to_account_info(),
+            authority: ctx.accounts.vault_authority.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(ctx.accounts.asset_token_program.to_account_info(), cpi_accounts, signer);
+        token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.token_mint.decimals)?;
+
+        let queue = ctx.accounts.vault.pool_queue_mut(&ctx.accounts.token_mint.key());
+        queue.reserved_assets = queue.reserved_assets.saturating_sub(amount);
+        let depositor = &mut ctx.accounts.depositor;
+        depositor.token_amount = depositor.token_amount.saturating_sub(amount);
+
+        emit!(WithdrawalClaimed {
+            vault: vault_key,
+            user: depositor.owner,
+            asset_mint: ctx.accounts.token_mint.key(),
+            epoch: ctx.accounts.withdrawal_request.epoch,
+            shares: ctx.accounts.withdrawal_request.shares,
+            amount,
+            depositor_principal: depositor.token_amount,
+            reserved_assets: queue.reserved_assets,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+}
+
+impl MintRegistry {
+    fn entry(&self, mint: &Pubkey) -> Result<&AcceptedMint> {
+        self.mints.iter().find(|entry| entry.mint == *mint).ok_or(error!(CustomError::InvalidMint))
+    }
+
+    fn entry_mut(&mut self, mint: &Pubkey) -> Result<&mut AcceptedMint> {
+        self.mints.iter_mut().find(|entry| entry.mint == *mint).ok_or(error!(CustomError::InvalidMint))
+    }
+}
+
+impl Vault {
+    fn pool_fees(&self, asset_mint: &Pubkey) -> &FeeState {
+        if *asset_mint == token::spl_token::native_mint::ID {
+            &self.sol_fees
+        } else {
+            &self.token_fees
+        }
+    }
+
+    fn pool_fees_mut(&mut self, asset_mint: &Pubkey) -> &mut FeeState {
+        if *asset_mint == token::spl_token::native_mint::ID {
+            &mut self.sol_fees
+        } else {
+            &mut self.token_fees
+        }
+    }
+
+    fn pool_queue(&self, asset_mint: &Pubkey) -> &WithdrawalQueue {
+        if *asset_mint == token::spl_token::native_mint::ID {
+            &self.sol_queue
+        } else {
+            &self.token_queue
+        }
+    }
+
+    fn pool_queue_mut(&mut self, asset_mint: &Pubkey) -> &mut WithdrawalQueue {
+        if *asset_mint == token::spl_token::native_mint::ID {
+            &mut self.sol_queue
+        } else {
+            &mut self.token_queue
+        }
+    }
+
+    // Fee shares owed since the last accrual and the high-water mark once they are billed, without
+    // touching the vault, so views can price against them too
+    fn accrued_fees(&self, asset_mint: &Pubkey, total_assets: u64, supply: u64, now: i64) -> Result<(u64, u64)> {
+        let fees = self.pool_fees(asset_mint);
+        let elapsed = now.saturating_sub(fees.last_accrual).max(0) as u128;
+
+        let total_shares = supply.checked_add(fees.pending_shares).ok_or(CustomError::MathOverflow)? as u128;
+        if total_shares == 0 || total_assets == 0 {
+            // An empty pool has no history to charge against
+            return Ok((0, 0));
+        }
+
+        let assets = total_assets as u128;
+        let mut fee_assets = assets * self.management_fee_bps as u128 * elapsed / (BPS_DENOMINATOR * SECONDS_PER_YEAR);
+
+        let price = assets * PRICE_SCALE / total_shares;
+        let high_water_mark = fees.high_water_mark as u128;
+        if high_water_mark > 0 && price > high_water_mark {
+            let gain = (price - high_water_mark) * total_shares / PRICE_SCALE;
+            fee_assets += gain * self.performance_fee_bps as u128 / BPS_DENOMINATOR;
+        }
+        let fee_assets = fee_assets.min(assets - 1);
+
+        // Mint enough shares that they are worth `fee_assets` once they exist
+        let fee_shares = fee_assets * total_shares / (assets - fee_assets);
+        let price = assets * PRICE_SCALE / (total_shares + fee_shares);
+        Ok((
+            u64::try_from(fee_shares).map_err(|_| error!(CustomError::MathOverflow))?,
+            u64::try_from(price.max(high_water_mark)).map_err(|_| error!(CustomError::MathOverflow))?,
+        ))
+    }
+
+    // Bills fees since the last accrual as pending shares and returns the supply to price against
+    fn accrue_fees(&mut self, asset_mint: &Pubkey, total_assets: u64, supply: u64, now: i64) -> Result<u64> {
+        let (fee_shares, high_water_mark) = self.accrued_fees(asset_mint, total_assets, supply, now)?;
+        let vault = self.vault;
+        let fees = self.pool_fees_mut(asset_mint);
+        fees.last_accrual = now;
+        fees.pending_shares = fees.pending_shares.checked_add(fee_shares).ok_or(CustomError::MathOverflow)?;
+        fees.high_water_mark = high_water_mark;
+
+        if fee_shares > 0 {
+            emit!(FeesAccrued {
+                vault,
+                asset_mint: *asset_mint,
+                total_assets,
+                fee_shares,
+                pending_shares: fees.pending_shares,
+                high_water_mark: fees.high_water_mark,
+                timestamp: now,
+            });
+        }
+
+        Ok(supply.checked_add(fees.pending_shares).ok_or(CustomError::MathOverflow)?)
+    }
+
+    // Shares withheld from the still-locked part of an exit. All redeemed shares are still burned:
+    // left alone the withheld part accrues to the remaining holders, sent to the treasury it joins the
+    // manager's pending fee shares. Locks follow the depositor's lots, not the shares themselves.
+    pub fn take_early_exit_penalty(&mut self, asset_mint: &Pubkey, locked_shares: u64) -> Result<u64> {
+        // A closing vault lets everyone out: liabilities have to reach zero before it can be closed
+        if locked_shares == 0 || self.status == VaultStatus::Closing {
+            return Ok(0);
+        }
+        require!(self.lockup.early_exit_penalty_bps > 0, CustomError::Locked);
+
+        let penalty_shares = (locked_shares as u128 * self.lockup.early_exit_penalty_bps as u128 / BPS_DENOMINATOR) as u64;
+        if self.lockup.penalty_destination == PenaltyDestination::Treasury {
+            let fees = self.pool_fees_mut(asset_mint);
+            fees.pending_shares = fees.pending_shares.checked_add(penalty_shares).ok_or(CustomError::MathOverflow)?;
+        }
+        Ok(penalty_shares)
+    }
+}
+
+// Each deposit's shares form a lot; past MAX_LOTS the two oldest merge under the later timestamp
+fn add_lot(lots: &mut Vec<Lot>, shares: u64, now: i64) {
+    lots.push(Lot { shares, deposit_time: now });
+    if lots.len() > MAX_LOTS {
+        let oldest = lots.remove(0);
+        lots[0].shares = lots[0].shares.saturating_add(oldest.shares);
+        lots[0].deposit_time = lots[0].deposit_time.max(oldest.deposit_time);
+    }
+}
+
+// Lots follow the depositor while shares move freely, so they are matched to the share balance
+// before use. Shares received by transfer or as fees lock from the moment they are first seen;
+// shares that left take the oldest lots with them.
+fn sync_lots(lots: &mut Vec<Lot>, balance: u64, now: i64) {
+    let tracked = lots.iter().map(|lot| lot.shares).sum::<u64>();
+    if balance > tracked {
+        add_lot(lots, balance - tracked, now);
+    } else {
+        take_lots(lots, tracked - balance);
+    }
+}
+
+// Puts lot pieces back in deposit order; past MAX_LOTS the oldest merge as in `add_lot`
+fn restore_lots(lots: &mut Vec<Lot>, pieces: &[Lot]) {
+    for piece in pieces {
+        let at = lots.partition_point(|lot| lot.deposit_time <= piece.deposit_time);
+        lots.insert(at, *piece);
+    }
+    while lots.len() > MAX_LOTS {
+        let oldest = lots.remove(0);
+        lots[0].shares = lots[0].shares.saturating_add(oldest.shares);
+        lots[0].deposit_time = lots[0].deposit_time.max(oldest.deposit_time);
+    }
+}
+
+// Drains lots oldest first and returns the pieces taken
+fn take_lots(lots: &mut Vec<Lot>, shares: u64) -> Vec<Lot> {
+    let mut remaining = shares;
+    let mut taken = Vec::new();
+    while remaining > 0 && !lots.is_empty() {
+        let piece = remaining.min(lots[0].shares);
+        taken.push(Lot { shares: piece, deposit_time: lots[0].deposit_time });
+        lots[0].shares -= piece;
+        remaining -= piece;
+        if lots[0].shares == 0 {
+            lots.remove(0);
+        }
+    }
+    taken
+}
+
+fn locked_shares(pieces: &[Lot], lock_period: i64, now: i64) -> u64 {
+    pieces.iter()
+        .filter(|lot| now < lot.deposit_time.saturating_add(lock_period))
+        .map(|lot| lot.shares)
+        .sum()
+}
+
+// Penalties come out of the most recently deposited pieces
+fn trim_newest(pieces: &mut Vec<Lot>, shares: u64) {
+    let mut remaining = shares;
+    while remaining > 0 && !pieces.is_empty() {
+        let newest = pieces.len() - 1;
+        let cut = remaining.min(pieces[newest].shares);
+        pieces[newest].shares -= cut;
+        remaining -= cut;
+        if pieces[newest].shares == 0 {
+            pieces.pop();
+        }
+    }
+}
+
+// Redeemed shares drain lots oldest first and the still-locked part is returned
+fn consume_lots(lots: &mut Vec<Lot>, shares: u64, lock_period: i64, now: i64) -> u64 {
+    locked_shares(&take_lots(lots, shares), lock_period, now)
+}
+
+// Share-weighted age of the lots in seconds
+fn weighted_age(lots: &[Lot], now: i64) -> Result<i64> {
+    let mut total: u128 = 0;
+    let mut weighted: u128 = 0;
+    for lot in lots {
+        let age = now.saturating_sub(lot.deposit_time).max(0) as u128;
+        total += lot.shares as u128;
+        weighted = weighted
+            .checked_add((lot.shares as u128).checked_mul(age).ok_or(CustomError::MathOverflow)?)
+            .ok_or(CustomError::MathOverflow)?;
+    }
+
+    if total == 0 {
+        return Ok(0);
+    }
+    Ok((weighted / total) as i64)
+}
+
+impl WithdrawalEpoch {
+    // A request's share of the settled assets, rounded down so the epoch never pays out more than it set aside
+    fn payout(&self, shares: u64) -> Result<u64> {
+        require!(self.settled, CustomError::WithdrawalNotSettled);
+        if self.shares == 0 {
+            return Ok(0);
+        }
+        let amount = shares as u128 * self.assets as u128 / self.shares as u128;
+        u64::try_from(amount).map_err(|_| error!(CustomError::MathOverflow))
+    }
+}
+
+fn open_position(
+    depositor: &mut Account<Depositor>,
+    positions: &mut Account<UserPositions>,
+    user: Pubkey,
+    vault: Pubkey,
+) -> Result<()> {
+    if depositor.is_initialized {
+        require_keys_eq!(depositor.owner, user, CustomError::Unauthorized);
+        require_keys_eq!(depositor.vault, vault, CustomError::VaultMismatch);
+        return Ok(());
+    }
+
+    depositor.owner = user;
+    depositor.is_initialized = true;
+    depositor.vault = vault;
+    depositor.sol_amount = 0;
+    depositor.token_amount = 0;
+    depositor.verified_root = [0; 32];
+    depositor.sol_lots = Vec::new();
+    depositor.token_lots = Vec::new();
+    track_position(positions, user, vault)
+}
+
+// Vaults without a root are open; otherwise the depositor proves membership once per root
+fn check_allowlist(vault: &Vault, depositor: &mut Depositor, proof: &[[u8; 32]]) -> Result<()> {
+    let Some(root) = vault.allowlist_root else {
+        return Ok(());
+    };
+    if depositor.verified_root == root {
+        return Ok(());
+    }
+
+    let leaf = keccak::hash(depositor.owner.as_ref()).to_bytes();
+    require!(verify_proof(proof, root, leaf), CustomError::NotAllowlisted);
+    depositor.verified_root = root;
+    Ok(())
+}
+
+// Pairs are hashed in sorted order so proofs don't need to carry left/right flags
+fn verify_proof(proof: &[[u8; 32]], root: [u8; 32], leaf: [u8; 32]) -> bool {
+    let computed = proof.iter().fold(leaf, |node, sibling| {
+        if node <= *sibling {
+            keccak::hashv(&[&node, sibling]).to_bytes()
+        } else {
+            keccak::hashv(&[sibling, &node]).to_bytes()
+        }
+    });
+    computed == root
+}
+
+// Singletons may only be created by the deployer: the upgrade authority recorded in this program's
+// ProgramData, or with the `fixed-owner` feature the key baked in through INITIAL_OWNER at build time
+fn require_deployer(signer: &Pubkey, program_data: &Option<Account<ProgramData>>) -> Result<()> {
+    #[cfg(feature = "fixed-owner")]
+    {
+        let _ = program_data;
+        let initial_owner = Pubkey::from_str(env!("INITIAL_OWNER")).map_err(|_| error!(CustomError::Unauthorized))?;
+        require_keys_eq!(*signer, initial_owner, CustomError::Unauthorized);
+    }
+
+    #[cfg(not(feature = "fixed-owner"))]
+    {
+        let program_data = program_data.as_ref().ok_or(error!(CustomError::Unauthorized))?;
+        require!(program_data.upgrade_authority_address == Some(*signer), CustomError::Unauthorized);
+    }
+    Ok(())
+}
+
+// A cap of zero means the vault is uncapped
+fn remaining_under_cap(cap: u64, used: u64) -> u64 {
+    if cap == 0 {
+        u64::MAX
+    } else {
+        cap.saturating_sub(used)
+    }
+}
+
+fn track_managed_vault(profile: &mut Account<ManagerProfile>, manager: Pubkey, vault: Pubkey) -> Result<()> {
+    if profile.manager == Pubkey::default() {
+        profile.manager = manager;
+    }
+    if !profile.vaults.contains(&vault) {
+        require!(profile.vaults.len() < MAX_VAULTS_PER_MANAGER, CustomError::TooManyManagedVaults);
+        profile.vaults.push(vault);
+    }
+    Ok(())
+}
+
+// The profile itself stays: its nonce still addresses the vaults this manager created
+fn untrack_managed_vault(profile: &mut Account<ManagerProfile>, vault: Pubkey) {
+    profile.vaults.retain(|managed| *managed != vault);
+}
+
+// Share mints are created with a pool's first deposit, so one that does not exist has no supply
+fn share_supply(share_mint: &AccountInfo) -> Result<u64> {
+    if share_mint.data_is_empty() {
+        return Ok(0);
+    }
+    Ok(Mint::try_deserialize(&mut &share_mint.data.borrow()[..])?.supply)
+}
+
+fn track_position(positions: &mut Account<UserPositions>, user: Pubkey, vault: Pubkey) -> Result<()> {
+    if positions.owner == Pubkey::default() {
+        positions.owner = user;
+    }
+    if !positions.vaults.contains(&vault) {
+        require!(positions.vaults.len() < MAX_POSITIONS_PER_USER, CustomError::TooManyPositions);
+        positions.vaults.push(vault);
+    }
+    Ok(())
+}
+
+// One virtual share and one virtual asset keep the first depositor from inflating the share price
+fn shares_for_assets(assets: u64, total_assets: u64, total_shares: u64) -> Result<u64> {
+    let shares = (assets as u128)
+        .checked_mul(total_shares as u128 + 1)
+        .ok_or(CustomError::MathOverflow)?
+        / (total_assets as u128 + 1);
+    u64::try_from(shares).map_err(|_| error!(CustomError::MathOverflow))
+}
+
+fn assets_for_shares(shares: u64, total_assets: u64, total_shares: u64) -> Result<u64> {
+    let assets = (shares as u128)
+        .checked_mul(total_assets as u128 + 1)
+        .ok_or(CustomError::MathOverflow)?
+        / (total_shares as u128 + 1);
+    u64::try_from(assets).map_err(|_| error!(CustomError::MathOverflow))
+}
+
+// What counts against a per-depositor cap: the principal on the position, or what the wallet's shares
+// are worth if that is more. Shares outlive `close_position`, so reopening a position starts from them.
+fn held_assets(principal: u64, shares: u64, total_assets: u64, total_shares: u64) -> Result<u64> {
+    Ok(principal.max(assets_for_shares(shares, total_assets, total_shares)?))
+}
+
+// `held_assets` for views, where the pool or the wallet's share account may not exist yet
+fn wallet_held_assets(
+    vault: &Vault,
+    asset_mint: Pubkey,
+    principal: u64,
+    share_mint: &AccountInfo,
+    custody: &AccountInfo,
+    share_account: &AccountInfo,
+) -> Result<u64> {
+    if share_mint.data_is_empty() || share_account.data_is_empty() {
+        return Ok(principal);
+    }
+    let shares = TokenAccount::try_deserialize(&mut &share_account.data.borrow()[..])?.amount;
+    let (total_assets, total_shares) = preview_pool(vault, asset_mint, share_supply(share_mint)?, custody)?;
+    held_assets(principal, shares, total_assets, total_shares)
+}
+
+// Counts fees owed since the last accrual so views quote what a deposit or redeem would actually get
+fn preview_pool(vault: &Vault, asset_mint: Pubkey, supply: u64, custody: &AccountInfo) -> Result<(u64, u64)> {
+    let total_assets = pool_assets(vault, asset_mint, custody)?;
+    let (fee_shares, _) = vault.accrued_fees(&asset_mint, total_assets, supply, Clock::get()?.unix_timestamp)?;
+    let total_shares = supply
+        .checked_add(vault.pool_fees(&asset_mint).pending_shares)
+        .and_then(|shares| shares.checked_add(fee_shares))
+        .ok_or(CustomError::MathOverflow)?;
+    Ok((total_assets, total_shares))
+}
+
+// Token-2022 mints may only carry extensions that leave custody and accounting predictable:
+// transfer fees are netted out of deposits, metadata and interest display are harmless.
+// Hooks, permanent delegates, default-frozen accounts, confidential transfers and closable mints are refused.
+fn require_supported_mint(mint: &AccountInfo) -> Result<()> {
+    if *mint.owner != token_interface::spl_token_2022::ID {
+        return Ok(());
+    }
+
+    let data = mint.try_borrow_data()?;
+    let state = StateWithExtensions::<token_interface::spl_token_2022::state::Mint>::unpack(&data)?;
+    for extension in state.get_extension_types()? {
+        match extension {
+            ExtensionType::TransferFeeConfig
+            | ExtensionType::InterestBearingConfig
+            | ExtensionType::MetadataPointer
+            | ExtensionType::TokenMetadata => {}
+            _ => return err!(CustomError::UnsupportedMintExtension),
+        }
+    }
+    Ok(())
+}
+
+// Fee withheld by a Token-2022 transfer-fee mint when moving `amount` this epoch
+fn transfer_fee(mint: &AccountInfo, amount: u64) -> Result<u64> {
+    if *mint.owner != token_interface::spl_token_2022::ID {
+        return Ok(0);
+    }
+
+    let data = mint.try_borrow_data()?;
+    let state = StateWithExtensions::<token_interface::spl_token_2022::state::Mint>::unpack(&data)?;
+    match state.get_extension::<TransferFeeConfig>() {
+        Ok(config) => config
+            .calculate_epoch_fee(Clock::get()?.epoch, amount)
+            .ok_or(error!(CustomError::MathOverflow)),
+        Err(_) => Ok(0),
+    }
+}
+
+// SOL custody holds no data; the rent-exempt reserve funded by `create_vault` backs no shares
+fn sol_custody_assets(custody: &AccountInfo) -> Result<u64> {
+    Ok(custody.lamports().saturating_sub(Rent::get()?.minimum_balance(0)))
+}
+
+// The native mint selects the SOL pool; any other mint selects the vault's token account.
+// Assets already set aside for settled withdrawals no longer back any shares.
+fn pool_assets(vault: &Vault, asset_mint: Pubkey, custody: &AccountInfo) -> Result<u64> {
+    let reserved = vault.pool_queue(&asset_mint).reserved_assets;
+    if asset_mint == token::spl_token::native_mint::ID {
+        let (expected, _) = Pubkey::find_program_address(&[b"vault_sol", vault.vault.as_ref()], &crate::ID);
+        require_keys_eq!(custody.key(), expected, CustomError::InvalidCustody);
+        return Ok(sol_custody_assets(custody)?.saturating_sub(reserved));
+    }
+
+    let (expected, _) = Pubkey::find_program_address(&[b"vault_token", vault.vault.as_ref()], &crate::ID);
+    require_keys_eq!(custody.key(), expected, CustomError::InvalidCustody);
+    let custody = token_interface::TokenAccount::try_deserialize(&mut &custody.data.borrow()[..])?;
+    require_keys_eq!(custody.mint, asset_mint, CustomError::InvalidMint);
+    Ok(custody.amount.saturating_sub(reserved))
+}
+
+#[derive(Accounts)]
+pub struct InitializeFactory<'info> {
+    #[account(
+        init, 
+        seeds = [b"vault_factory"], 
+        bump, 
+        payer = owner, 
+        space = 8 + 32 + 4 + 4 + 2 + 2 + 1 + 1 + (1 + 32)
+    )]
+    pub factory: Account<'info, Factory>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    /// This program's ProgramData; may be omitted when built with `fixed-owner`
+    #[account(seeds = [crate::ID.as_ref()], bump, seeds::program = bpf_loader_upgradeable::ID)]
+    pub program_data: Option<Account<'info, ProgramData>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeMintRegistry<'info> {
+    #[account(seeds = [b"vault_factory"], bump)]
+    pub factory: Account<'info, Factory>,
+    #[account(
+        init,
+        seeds = [b"mint_registry"],
+        bump,
+        payer = owner,
+        space = 8 + 4 + (32 + 1 + 1 + 8 + 8) * MAX_ACCEPTED_MINTS
+    )]
+    pub mint_registry: Account<'info, MintRegistry>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ConfigureMint<'info> {
+    #[account(seeds = [b"vault_factory"], bump)]
+    pub factory: Account<'info, Factory>,
+    #[account(mut, seeds = [b"mint_registry"], bump)]
+    pub mint_registry: Account<'info, MintRegistry>,
+    pub mint: InterfaceAccount<'info, token_interface::Mint>,
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(manager: Pubkey)]
+pub struct CreateVault<'info> {
+    #[account(mut, seeds = [b"vault_factory"], bump)]
+    pub factory: Account<'info, Factory>,
+    #[account(seeds = [b"mint_registry"], bump)]
+    pub mint_registry: Account<'info, MintRegistry>,
+    #[account(mut)] 
+    pub owner: Signer<'info>,
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = 8 + 32 + 4 + 4 + (32 * MAX_VAULTS_PER_MANAGER),
+        seeds = [b"manager_profile", manager.as_ref()],
+        bump
+    )]
+    pub manager_profile: Account<'info, ManagerProfile>,
+    #[account(init, seeds = [b"vault", manager.as_ref(), &manager_profile.next_nonce.to_le_bytes()], bump, payer = owner, space = 8 + 32 + 8 + 4 + 32 + 8 + 2 + 2 + (8 + 8 + 8) * 2 + 1 + 1 + (8 * 4) + (1 + 32) + 32 + (8 + 2 + 1) + (8 + 8) * 2 + 32 + (1 + 32) + 4 + 1)]
+    pub vault: Account<'info, Vault>,
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = 8 + 32 + 4 + (1 + 32) + 4 + (32 * VAULTS_PER_PAGE),
+        seeds = [b"vault_registry_page", factory.key().as_ref(), &(factory.vault_count / VAULTS_PER_PAGE as u32).to_le_bytes()],
+        bump
+    )]
+    pub registry_page: Account<'info, VaultRegistryPage>,
+    /// Only required when `registry_page` is being created past page 0
+    #[account(
+        mut,
+        seeds = [b"vault_registry_page", factory.key().as_ref(), &(factory.vault_count / VAULTS_PER_PAGE as u32).saturating_sub(1).to_le_bytes()],
+        bump
+    )]
+    pub previous_page: Option<Account<'info, VaultRegistryPage>>,
+    #[account(mut, seeds = [b"vault_sol", vault.key().as_ref()], bump)]
+    pub vault_sol_account: SystemAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(page_index: u32)]
+pub struct ListVaults<'info> {
+    #[account(seeds = [b"vault_factory"], bump)]
+    pub factory: Account<'info, Factory>,
+    #[account(
+        seeds = [b"vault_registry_page", factory.key().as_ref(), &page_index.to_le_bytes()],
+        bump,
+        has_one = factory
+    )]
+    pub registry_page: Account<'info, VaultRegistryPage>,
+}
+
+#[derive(Accounts)]
+pub struct DepositSol<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(seeds = [b"vault_factory"], bump)]
+    pub factory: Account<'info, Factory>,
+
+    #[account(mut, seeds = [b"vault", vault.creator.as_ref(), &vault.nonce.to_le_bytes()], bump)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + 32 + 1 + 8 + 8 + 8 + 32 + 32 + (4 + (8 + 8) * MAX_LOTS) * 2,
+        seeds = [b"depositor", vault.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub depositor: Account<'info, Depositor>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + 32 + 4 + (32 * MAX_POSITIONS_PER_USER),
+        seeds = [b"user_positions", user.key().as_ref()],
+        bump
+    )]
+    pub user_positions: Account<'info, UserPositions>,
+
+    /// SOL custody owned by this vault alone
+    #[account(mut, seeds = [b"vault_sol", vault.key().as_ref()], bump)]
+    pub vault_sol_account: SystemAccount<'info>,
+
+    /// CHECK: PDA signing for this vault's token accounts, holds no data
+    #[account(seeds = [b"vault_authority", vault.key().as_ref()], bump)]
+    pub vault_authority: UncheckedAccount<'info>,
+
+    /// Shares of the SOL pool, keyed by the native mint
+    #[account(
+        init_if_needed,
+        payer = user,
+        mint::decimals = 9,
+        mint::authority = vault_authority,
+        seeds = [b"share_mint", vault.key().as_ref(), token::spl_token::native_mint::ID.as_ref()],
+        bump
+    )]
+    pub share_mint: Account<'info, Mint>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        associated_token::mint = share_mint,
+        associated_token::authority = user
+    )]
+    pub user_share_account: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct DepositToken<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(seeds = [b"vault_factory"], bump)]
+    pub factory: Account<'info, Factory>,
+
+    #[account(mut, seeds = [b"mint_registry"], bump)]
+    pub mint_registry: Account<'info, MintRegistry>,
+
+    #[account(mut, seeds = [b"vault", vault.creator.as_ref(), &vault.nonce.to_le_bytes()], bump)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + 32 + 1 + 8 + 8 + 8 + 32 + 32 + (4 + (8 + 8) * MAX_LOTS) * 2,
+        seeds = [b"depositor", vault.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub depositor: Account<'info, Depositor>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + 32 + 4 + (32 * MAX_POSITIONS_PER_USER),
+        seeds = [b"user_positions", user.key().as_ref()],
+        bump
+    )]
+    pub user_positions: Account<'info, UserPositions>,
+
+    /// CHECK: PDA signing for this vault's token accounts, holds no data
+    #[account(seeds = [b"vault_authority", vault.key().as_ref()], bump)]
+    pub vault_authority: UncheckedAccount<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        token::mint = token_mint,
+        token::authority = vault_authority,
+        token::token_program = asset_token_program,
+        seeds = [b"vault_token", vault.key().as_ref()],
+        bump
+    )]
+    pub vault_token_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, token::mint = token_mint, token::token_program = asset_token_program)]
+    pub user_token_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        mint::decimals = token_mint.decimals,
+        mint::authority = vault_authority,
+        seeds = [b"share_mint", vault.key().as_ref(), token_mint.key().as_ref()],
+        bump
+    )]
+    pub share_mint: Account<'info, Mint>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        associated_token::mint = share_mint,
+        associated_token::authority = user
+    )]
+    pub user_share_account: Account<'info, TokenAccount>,
+
+    #[account(mint::token_program = asset_token_program)]
+    pub token_mint: InterfaceAccount<'info, token_interface::Mint>,
+    /// Legacy Token or Token-2022, whichever owns `token_mint`
+    pub asset_token_program: Interface<'info, TokenInterface>,
+    /// Share mints always live under the legacy Token program
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawSol<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(seeds = [b"vault_factory"], bump)]
+    pub factory: Account<'info, Factory>,
+
+    #[account(mut, seeds = [b"vault", vault.creator.as_ref(), &vault.nonce.to_le_bytes()], bump)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + 32 + 1 + 8 + 8 + 8 + 32 + 32 + (4 + (8 + 8) * MAX_LOTS) * 2,
+        seeds = [b"depositor", vault.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub depositor: Account<'info, Depositor>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + 32 + 4 + (32 * MAX_POSITIONS_PER_USER),
+        seeds = [b"user_positions", user.key().as_ref()],
+        bump
+    )]
+    pub user_positions: Account<'info, UserPositions>,
+
+    #[account(mut, seeds = [b"vault_sol", vault.key().as_ref()], bump)]
+    pub vault_sol_account: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"share_mint", vault.key().as_ref(), token::spl_token::native_mint::ID.as_ref()],
+        bump
+    )]
+    pub share_mint: Account<'info, Mint>,
+
+    #[account(mut, associated_token::mint = share_mint, associated_token::authority = user)]
+    pub user_share_account: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawToken<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(seeds = [b"vault_factory"], bump)]
+    pub factory: Account<'info, Factory>,
+
+    #[account(mut, seeds = [b"mint_registry"], bump)]
+    pub mint_registry: Account<'info, MintRegistry>,
+
+    #[account(mut, seeds = [b"vault", vault.creator.as_ref(), &vault.nonce.to_le_bytes()], bump)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + 32 + 1 + 8 + 8 + 8 + 32 + 32 + (4 + (8 + 8) * MAX_LOTS) * 2,
+        seeds = [b"depositor", vault.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub depositor: Account<'info, Depositor>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + 32 + 4 + (32 * MAX_POSITIONS_PER_USER),
+        seeds = [b"user_positions", user.key().as_ref()],
+        bump
+    )]
+    pub user_positions: Account<'info, UserPositions>,
+
+    /// CHECK: PDA signing for this vault's token accounts, holds no data
+    #[account(seeds = [b"vault_authority", vault.key().as_ref()], bump)]
+    pub vault_authority: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        token::mint = token_mint,
+        token::authority = vault_authority,
+        token::token_program = asset_token_program,
+        seeds = [b"vault_token", vault.key().as_ref()],
+        bump
+    )]
+    pub vault_token_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, token::mint = token_mint, token::token_program = asset_token_program)]
+    pub user_token_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, seeds = [b"share_mint", vault.key().as_ref(), token_mint.key().as_ref()], bump)]
+    pub share_mint: Account<'info, Mint>,
+
+    #[account(mut, associated_token::mint = share_mint, associated_token::authority = user)]
+    pub user_share_account: Account<'info, TokenAccount>,
+
+    #[account(mint::token_program = asset_token_program)]
+    pub token_mint: InterfaceAccount<'info, token_interface::Mint>,
+    pub asset_token_program: Interface<'info, TokenInterface>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetFeeLimits<'info> {
+    #[account(mut, seeds = [b"vault_factory"], bump)]
+    pub factory: Account<'info, Factory>,
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetFactoryPause<'info> {
+    #[account(mut, seeds = [b"vault_factory"], bump)]
+    pub factory: Account<'info, Factory>,
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateFactoryAuthority<'info> {
+    #[account(mut, seeds = [b"vault_factory"], bump)]
+    pub factory: Account<'info, Factory>,
+    /// The owner, or the pending owner when accepting
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateVaultManager<'info> {
+    #[account(mut, seeds = [b"vault", vault.creator.as_ref(), &vault.nonce.to_le_bytes()], bump)]
+    pub vault: Account<'info, Vault>,
+    /// The current manager
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptManager<'info> {
+    #[account(mut, seeds = [b"vault", vault.creator.as_ref(), &vault.nonce.to_le_bytes()], bump)]
+    pub vault: Account<'info, Vault>,
+    #[account(mut, seeds = [b"manager_profile", vault.manager.as_ref()], bump)]
+    pub previous_profile: Account<'info, ManagerProfile>,
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + 32 + 4 + 4 + (32 * MAX_VAULTS_PER_MANAGER),
+        seeds = [b"manager_profile", authority.key().as_ref()],
+        bump
+    )]
+    pub manager_profile: Account<'info, ManagerProfile>,
+    /// The pending manager
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RenounceManager<'info> {
+    #[account(mut, seeds = [b"vault", vault.creator.as_ref(), &vault.nonce.to_le_bytes()], bump)]
+    pub vault: Account<'info, Vault>,
+    #[account(mut, seeds = [b"manager_profile", vault.manager.as_ref()], bump)]
+    pub manager_profile: Account<'info, ManagerProfile>,
+    /// The current manager
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(new_manager: Pubkey)]
+pub struct RotateManager<'info> {
+    #[account(seeds = [b"vault_factory"], bump)]
+    pub factory: Account<'info, Factory>,
+    #[account(mut, seeds = [b"vault", vault.creator.as_ref(), &vault.nonce.to_le_bytes()], bump)]
+    pub vault: Account<'info, Vault>,
+    /// Omitted once the manager has renounced
+    #[account(mut, seeds = [b"manager_profile", vault.manager.as_ref()], bump)]
+    pub previous_profile: Option<Account<'info, ManagerProfile>>,
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + 32 + 4 + 4 + (32 * MAX_VAULTS_PER_MANAGER),
+        seeds = [b"manager_profile", new_manager.as_ref()],
+        bump
+    )]
+    pub manager_profile: Account<'info, ManagerProfile>,
+    /// Either the vault manager or the factory owner
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetVaultPause<'info> {
+    #[account(seeds = [b"vault_factory"], bump)]
+    pub factory: Account<'info, Factory>,
+    #[account(mut, seeds = [b"vault", vault.creator.as_ref(), &vault.nonce.to_le_bytes()], bump)]
+    pub vault: Account<'info, Vault>,
+    /// Either the vault manager or the factory owner
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct BeginClose<'info> {
+    #[account(seeds = [b"vault_factory"], bump)]
+    pub factory: Account<'info, Factory>,
+    #[account(mut, seeds = [b"vault", vault.creator.as_ref(), &vault.nonce.to_le_bytes()], bump)]
+    pub vault: Account<'info, Vault>,
+    /// Either the vault manager or the factory owner
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseVault<'info> {
+    #[account(mut)]
+    pub manager: Signer<'info>,
+
+    #[account(seeds = [b"vault_factory"], bump)]
+    pub factory: Account<'info, Factory>,
+
+    #[account(mut, close = manager, seeds = [b"vault", vault.creator.as_ref(), &vault.nonce.to_le_bytes()], bump)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [b"vault_registry_page", factory.key().as_ref(), &(vault.index / VAULTS_PER_PAGE as u32).to_le_bytes()],
+        bump,
+        has_one = factory
+    )]
+    pub registry_page: Account<'info, VaultRegistryPage>,
+
+    #[account(mut, seeds = [b"manager_profile", vault.manager.as_ref()], bump)]
+    pub manager_profile: Account<'info, ManagerProfile>,
+
+    #[account(mut, seeds = [b"vault_sol", vault.key().as_ref()], bump)]
+    pub vault_sol_account: SystemAccount<'info>,
+
+    /// CHECK: PDA signing for this vault's token accounts, holds no data
+    #[account(seeds = [b"vault_authority", vault.key().as_ref()], bump)]
+    pub vault_authority: UncheckedAccount<'info>,
+
+    /// CHECK: may not exist yet; read in `share_supply`
+    #[account(seeds = [b"share_mint", vault.key().as_ref(), token::spl_token::native_mint::ID.as_ref()], bump)]
+    pub sol_share_mint: UncheckedAccount<'info>,
+
+    /// CHECK: may not exist yet; read in `share_supply`
+    #[account(seeds = [b"share_mint", vault.key().as_ref(), vault.token_mint.as_ref()], bump)]
+    pub token_share_mint: UncheckedAccount<'info>,
+
+    /// CHECK: closed if it exists
+    #[account(mut, seeds = [b"withdrawal_escrow", vault.key().as_ref(), token::spl_token::native_mint::ID.as_ref()], bump)]
+    pub sol_escrow: UncheckedAccount<'info>,
+
+    /// CHECK: closed if it exists
+    #[account(mut, seeds = [b"withdrawal_escrow", vault.key().as_ref(), vault.token_mint.as_ref()], bump)]
+    pub token_escrow: UncheckedAccount<'info>,
+
+    /// CHECK: emptied and closed if it exists
+    #[account(mut, seeds = [b"vault_token", vault.key().as_ref()], bump)]
+    pub vault_token_account: UncheckedAccount<'info>,
+
+    /// Only required when token custody still holds a balance
+    #[account(mut, token::mint = token_mint, token::authority = manager, token::token_program = asset_token_program)]
+    pub manager_token_account: Option<InterfaceAccount<'info, token_interface::TokenAccount>>,
+
+    #[account(address = vault.token_mint, mint::token_program = asset_token_program)]
+    pub token_mint: InterfaceAccount<'info, token_interface::Mint>,
+    pub asset_token_program: Interface<'info, TokenInterface>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SyncPosition<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(seeds = [b"vault", vault.creator.as_ref(), &vault.nonce.to_le_bytes()], bump)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + 32 + 1 + 8 + 8 + 8 + 32 + 32 + (4 + (8 + 8) * MAX_LOTS) * 2,
+        seeds = [b"depositor", vault.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub depositor: Account<'info, Depositor>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + 32 + 4 + (32 * MAX_POSITIONS_PER_USER),
+        seeds = [b"user_positions", user.key().as_ref()],
+        bump
+    )]
+    pub user_positions: Account<'info, UserPositions>,
+
+    /// CHECK: only used to pick the pool; the native mint means SOL
+    pub asset_mint: UncheckedAccount<'info>,
+
+    #[account(seeds = [b"share_mint", vault.key().as_ref(), asset_mint.key().as_ref()], bump)]
+    pub share_mint: Account<'info, Mint>,
+
+    #[account(associated_token::mint = share_mint, associated_token::authority = user)]
+    pub user_share_account: Account<'info, TokenAccount>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClosePosition<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    /// CHECK: the position's vault, which may already be closed; read in `close_position` while it exists
+    pub vault: UncheckedAccount<'info>,
+
+    #[account(mut, close = user, seeds = [b"depositor", vault.key().as_ref(), user.key().as_ref()], bump)]
+    pub depositor: Account<'info, Depositor>,
+
+    #[account(mut, seeds = [b"user_positions", user.key().as_ref()], bump)]
+    pub user_positions: Account<'info, UserPositions>,
+
+    /// CHECK: must not exist while the vault is open
+    #[account(seeds = [b"withdrawal_request", vault.key().as_ref(), token::spl_token::native_mint::ID.as_ref(), user.key().as_ref()], bump)]
+    pub sol_request: UncheckedAccount<'info>,
+
+    /// CHECK: must not exist while the vault is open
+    #[account(seeds = [b"withdrawal_request", vault.key().as_ref(), token_mint.key().as_ref(), user.key().as_ref()], bump)]
+    pub token_request: UncheckedAccount<'info>,
+
+    /// CHECK: the vault's token mint, matched against the vault in `close_position`
+    pub token_mint: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetLockup<'info> {
+    #[account(mut, seeds = [b"vault", vault.creator.as_ref(), &vault.nonce.to_le_bytes()], bump)]
+    pub vault: Account<'info, Vault>,
+    pub manager: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetAllowlistRoot<'info> {
+    #[account(mut, seeds = [b"vault", vault.creator.as_ref(), &vault.nonce.to_le_bytes()], bump)]
+    pub vault: Account<'info, Vault>,
+    pub manager: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetDepositCaps<'info> {
+    #[account(seeds = [b"vault_factory"], bump)]
+    pub factory: Account<'info, Factory>,
+    #[account(mut, seeds = [b"vault", vault.creator.as_ref(), &vault.nonce.to_le_bytes()], bump)]
+    pub vault: Account<'info, Vault>,
+    /// Either the vault manager or the factory owner
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(user: Pubkey)]
+pub struct GetDepositCapacity<'info> {
+    #[account(seeds = [b"mint_registry"], bump)]
+    pub mint_registry: Account<'info, MintRegistry>,
+    #[account(seeds = [b"vault", vault.creator.as_ref(), &vault.nonce.to_le_bytes()], bump)]
+    pub vault: Account<'info, Vault>,
+    #[account(seeds = [b"depositor", vault.key().as_ref(), user.as_ref()], bump)]
+    pub depositor: Option<Account<'info, Depositor>>,
+    /// CHECK: may not exist yet; read in `share_supply`
+    #[account(seeds = [b"share_mint", vault.key().as_ref(), token::spl_token::native_mint::ID.as_ref()], bump)]
+    pub sol_share_mint: UncheckedAccount<'info>,
+    /// CHECK: may not exist yet; read in `share_supply`
+    #[account(seeds = [b"share_mint", vault.key().as_ref(), vault.token_mint.as_ref()], bump)]
+    pub token_share_mint: UncheckedAccount<'info>,
+    #[account(seeds = [b"vault_sol", vault.key().as_ref()], bump)]
+    pub vault_sol_account: SystemAccount<'info>,
+    /// CHECK: may not exist yet; read in `pool_assets`
+    #[account(seeds = [b"vault_token", vault.key().as_ref()], bump)]
+    pub vault_token_account: UncheckedAccount<'info>,
+    /// CHECK: the wallet's SOL share account, which may not exist
+    #[account(address = get_associated_token_address(&user, &sol_share_mint.key()))]
+    pub sol_share_account: UncheckedAccount<'info>,
+    /// CHECK: the wallet's token share account, which may not exist
+    #[account(address = get_associated_token_address(&user, &token_share_mint.key()))]
+    pub token_share_account: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetVaultFees<'info> {
+    #[account(seeds = [b"vault_factory"], bump)]
+    pub factory: Account<'info, Factory>,
+    #[account(mut, seeds = [b"vault", vault.creator.as_ref(), &vault.nonce.to_le_bytes()], bump)]
+    pub vault: Account<'info, Vault>,
+    pub manager: Signer<'info>,
+    /// CHECK: may not exist yet; read in `share_supply`
+    #[account(seeds = [b"share_mint", vault.key().as_ref(), token::spl_token::native_mint::ID.as_ref()], bump)]
+    pub sol_share_mint: UncheckedAccount<'info>,
+    /// CHECK: may not exist yet; read in `share_supply`
+    #[account(seeds = [b"share_mint", vault.key().as_ref(), vault.token_mint.as_ref()], bump)]
+    pub token_share_mint: UncheckedAccount<'info>,
+    #[account(seeds = [b"vault_sol", vault.key().as_ref()], bump)]
+    pub vault_sol_account: SystemAccount<'info>,
+    /// CHECK: may not exist yet; read in `pool_assets`
+    #[account(seeds = [b"vault_token", vault.key().as_ref()], bump)]
+    pub vault_token_account: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AccrueFees<'info> {
+    #[account(mut, seeds = [b"vault", vault.creator.as_ref(), &vault.nonce.to_le_bytes()], bump)]
+    pub vault: Account<'info, Vault>,
+    /// CHECK: only used to pick the pool; the native mint means SOL
+    pub asset_mint: UncheckedAccount<'info>,
+    #[account(seeds = [b"share_mint", vault.key().as_ref(), asset_mint.key().as_ref()], bump)]
+    pub share_mint: Account<'info, Mint>,
+    /// CHECK: checked against the pool's custody PDA in `pool_assets`
+    pub custody: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimFees<'info> {
+    #[account(mut)]
+    pub manager: Signer<'info>,
+    #[account(mut, seeds = [b"vault", vault.creator.as_ref(), &vault.nonce.to_le_bytes()], bump)]
+    pub vault: Account<'info, Vault>,
+    /// CHECK: PDA signing for this vault's token accounts, holds no data
+    #[account(seeds = [b"vault_authority", vault.key().as_ref()], bump)]
+    pub vault_authority: UncheckedAccount<'info>,
+    /// CHECK: only used to pick the pool; the native mint means SOL
+    pub asset_mint: UncheckedAccount<'info>,
+    #[account(mut, seeds = [b"share_mint", vault.key().as_ref(), asset_mint.key().as_ref()], bump)]
+    pub share_mint: Account<'info, Mint>,
+    /// CHECK: checked against the pool's custody PDA in `pool_assets`
+    pub custody: UncheckedAccount<'info>,
+    #[account(
+        init_if_needed,
+        payer = manager,
+        associated_token::mint = share_mint,
+        associated_token::authority = manager
+    )]
+    pub manager_share_account: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RequestWithdraw<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(seeds = [b"vault_factory"], bump)]
+    pub factory: Account<'info, Factory>,
+
+    #[account(mut, seeds = [b"vault", vault.creator.as_ref(), &vault.nonce.to_le_bytes()], bump)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + 32 + 1 + 8 + 8 + 8 + 32 + 32 + (4 + (8 + 8) * MAX_LOTS) * 2,
+        seeds = [b"depositor", vault.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub depositor: Account<'info, Depositor>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + 32 + 4 + (32 * MAX_POSITIONS_PER_USER),
+        seeds = [b"user_positions", user.key().as_ref()],
+        bump
+    )]
+    pub user_positions: Account<'info, UserPositions>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + 32 + 32 + 32 + 8 + 8 + 4 + (8 + 8) * MAX_LOTS,
+        seeds = [b"withdrawal_request", vault.key().as_ref(), asset_mint.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub withdrawal_request: Account<'info, WithdrawalRequest>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + 32 + 32 + 8 + 8 + 8 + 1,
+        seeds = [b"withdrawal_epoch", vault.key().as_ref(), asset_mint.key().as_ref(), &vault.pool_queue(&asset_mint.key()).epoch.to_le_bytes()],
+        bump
+    )]
+    pub withdrawal_epoch: Account<'info, WithdrawalEpoch>,
+
+    /// CHECK: only used to pick the pool; the native mint means SOL
+    pub asset_mint: UncheckedAccount<'info>,
+
+    /// CHECK: checked against the pool's custody PDA in `pool_assets`
+    pub custody: UncheckedAccount<'info>,
+
+    /// CHECK: PDA signing for this vault's token accounts, holds no data
+    #[account(seeds = [b"vault_authority", vault.key().as_ref()], bump)]
+    pub vault_authority: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [b"share_mint", vault.key().as_ref(), asset_mint.key().as_ref()], bump)]
+    pub share_mint: Account<'info, Mint>,
 
-        factory.vaults.push(vault.key());
-        factory.vault_count += 1;
-        factory.vault_managers.push(manager);
+    #[account(mut, associated_token::mint = share_mint, associated_token::authority = user)]
+    pub user_share_account: Account<'info, TokenAccount>,
 
-        Ok(())
-    }
+    #[account(
+        init_if_needed,
+        payer = user,
+        token::mint = share_mint,
+        token::authority = vault_authority,
+        seeds = [b"withdrawal_escrow", vault.key().as_ref(), asset_mint.key().as_ref()],
+        bump
+    )]
+    pub escrow_share_account: Account<'info, TokenAccount>,
 
-    pub fn deposit_sol(ctx: Context<DepositSol>, amount: u64) -> Result<()> {
-        require!(amount > 0, CustomError::InvalidAmount);
-        
-        let vault = &mut ctx.accounts.vault;
-        let depositor = &mut ctx.accounts.depositor;
-        let user = &mut ctx.accounts.user;
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
 
-        if depositor.is_initialized {
-            require_keys_eq!(depositor.owner, user.key(), CustomError::Unauthorized);
-        } else {
-            depositor.owner = *ctx.accounts.user.key;
-            depositor.is_initialized = true;
-            depositor.vault_pda = vault.key();
-            depositor.sol_amount = 0;
-            depositor.usdc_amount = 0;
-        }
+#[derive(Accounts)]
+pub struct CancelWithdrawal<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
 
-        depositor.sol_amount = depositor.sol_amount.checked_add(amount).ok_or(CustomError::MathOverflow)?;
-        depositor.deposit_time = Clock::get()?.unix_timestamp;
-        
-        vault.total_deposit += amount;
+    #[account(seeds = [b"vault_factory"], bump)]
+    pub factory: Account<'info, Factory>,
 
-        **ctx.accounts.user.to_account_info().try_borrow_mut_lamports()? -= amount;
-        **ctx.accounts.vault.to_account_info().try_borrow_mut_lamports()? += amount;
-        Ok(())
-    }
+    #[account(seeds = [b"vault", vault.creator.as_ref(), &vault.nonce.to_le_bytes()], bump)]
+    pub vault: Account<'info, Vault>,
 
-    pub fn deposit_usdc(ctx: Context<DepositUsdc>, amount: u64) -> Result<()> {
-        require!(amount > 0, CustomError::InvalidAmount);
-        
-        let depositor = &mut ctx.accounts.depositor;
-        let user = &mut ctx.accounts.user;
+    #[account(mut, seeds = [b"depositor", vault.key().as_ref(), user.key().as_ref()], bump)]
+    pub depositor: Account<'info, Depositor>,
 
-        if depositor.is_initialized {
-            require_keys_eq!(depositor.owner, user.key(), CustomError::Unauthorized);
-        } else {
-            depositor.owner = *ctx.accounts.user.key;
-            depositor.is_initialized = true;
-            depositor.vault_pda = ctx.accounts.vault_usdc_account.key();
-            depositor.sol_amount = 0;
-            depositor.usdc_amount = 0;
-        }
+    #[account(
+        mut,
+        close = user,
+        seeds = [b"withdrawal_request", vault.key().as_ref(), withdrawal_request.asset_mint.as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub withdrawal_request: Account<'info, WithdrawalRequest>,
 
-        require_keys_eq!(ctx.accounts.usdc_mint.key(), Pubkey::from_str(USDC_MINT).unwrap(), CustomError::InvalidMint);
-        require_keys_eq!(ctx.accounts.user_usdc_account.mint, ctx.accounts.usdc_mint.key(), CustomError::InvalidMint);
-        
-        let cpi_accounts = Transfer {
-            from: ctx.accounts.user_usdc_account.to_account_info(),
-            to: ctx.accounts.vault_usdc_account.to_account_info(),
-            authority: ctx.accounts.user.to_account_info(),
-        };
-        let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
-        token::transfer(cpi_ctx, amount)?;
+    #[account(
+        mut,
+        seeds = [b"withdrawal_epoch", vault.key().as_ref(), withdrawal_request.asset_mint.as_ref(), &withdrawal_request.epoch.to_le_bytes()],
+        bump
+    )]
+    pub withdrawal_epoch: Account<'info, WithdrawalEpoch>,
 
-        depositor.usdc_amount = depositor.usdc_amount.checked_add(amount).ok_or(CustomError::MathOverflow)?;
-        depositor.deposit_time = Clock::get()?.unix_timestamp;
-        Ok(())
-    }
+    /// CHECK: PDA signing for this vault's token accounts, holds no data
+    #[account(seeds = [b"vault_authority", vault.key().as_ref()], bump)]
+    pub vault_authority: UncheckedAccount<'info>,
 
-    pub fn withdraw(ctx: Context<Withdraw>, sol_amount: u64, usdc_amount: u64) -> Result<()> {
-        let depositor = &mut ctx.accounts.depositor;
-        require_keys_eq!(depositor.owner, ctx.accounts.user.key(), CustomError::Unauthorized);
-
-        // Withdraw SOL
-        if sol_amount > 0 {
-            require!(depositor.sol_amount >= sol_amount, CustomError::InsufficientBalance);
-            
-            let bump = ctx.bumps.vault_pda;
-            let seeds = &[b"vault_pda".as_ref(), &[bump]];
-            let signer = &[&seeds[..]];
+    #[account(
+        mut,
+        seeds = [b"withdrawal_escrow", vault.key().as_ref(), withdrawal_request.asset_mint.as_ref()],
+        bump
+    )]
+    pub escrow_share_account: Account<'info, TokenAccount>,
 
-            let ix = anchor_lang::solana_program::system_instruction::transfer(
-                &ctx.accounts.vault_pda.key(),
-                &ctx.accounts.user.key(),
-                sol_amount,
-            );
-            anchor_lang::solana_program::program::invoke_signed(
-                &ix,
-                &[
-                    ctx.accounts.vault_pda.to_account_info(),
-                    ctx.accounts.user.to_account_info(),
-                ],
-                signer,
-            )?;
+    #[account(mut, associated_token::mint = escrow_share_account.mint, associated_token::authority = user)]
+    pub user_share_account: Account<'info, TokenAccount>,
 
-            depositor.sol_amount = depositor.sol_amount.checked_sub(sol_amount).ok_or(CustomError::MathOverflow)?;
-        }
+    pub token_program: Program<'info, Token>,
+}
 
-        // Withdraw USDC
-        if usdc_amount > 0 {
-            require!(depositor.usdc_amount >= usdc_amount, CustomError::InsufficientBalance);
+#[derive(Accounts)]
+pub struct SettleWithdrawals<'info> {
+    #[account(seeds = [b"vault_factory"], bump)]
+    pub factory: Account<'info, Factory>,
 
-            require_keys_eq!(ctx.accounts.usdc_mint.key(), Pubkey::from_str(USDC_MINT).unwrap(), CustomError::InvalidMint);
-            require_keys_eq!(ctx.accounts.user_usdc_account.mint, ctx.accounts.usdc_mint.key(), CustomError::InvalidMint);
-            require_keys_eq!(ctx.accounts.vault_usdc_account.mint, ctx.accounts.usdc_mint.key(), CustomError::InvalidMint);
+    #[account(mut, seeds = [b"mint_registry"], bump)]
+    pub mint_registry: Account<'info, MintRegistry>,
 
-            let bump = ctx.bumps.vault_usdc_account;
-            let seeds = &[b"vault_usdc_account".as_ref(), &[bump]];
-            let signer = &[&seeds[..]];
+    #[account(mut, seeds = [b"vault", vault.creator.as_ref(), &vault.nonce.to_le_bytes()], bump)]
+    pub vault: Account<'info, Vault>,
 
-            let cpi_accounts = Transfer {
-                from: ctx.accounts.vault_usdc_account.to_account_info(),
-                to: ctx.accounts.user_usdc_account.to_account_info(),
-                authority: ctx.accounts.vault_usdc_account.to_account_info(),
-            };
+    #[account(
+        mut,
+        seeds = [b"withdrawal_epoch", vault.key().as_ref(), asset_mint.key().as_ref(), &vault.pool_queue(&asset_mint.key()).epoch.to_le_bytes()],
+        bump
+    )]
+    pub withdrawal_epoch: Account<'info, WithdrawalEpoch>,
 
-            let cpi_ctx = CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), cpi_accounts, signer);
-            token::transfer(cpi_ctx, usdc_amount)?;
+    /// CHECK: only used to pick the pool; the native mint means SOL
+    pub asset_mint: UncheckedAccount<'info>,
 
-            depositor.usdc_amount = depositor.usdc_amount.checked_sub(usdc_amount).ok_or(CustomError::MathOverflow)?;
-        }
+    /// CHECK: checked against the pool's custody PDA in `pool_assets`
+    pub custody: UncheckedAccount<'info>,
 
-        Ok(())
-    }
-}
+    /// CHECK: PDA signing for this vault's token accounts, holds no data
+    #[account(seeds = [b"vault_authority", vault.key().as_ref()], bump)]
+    pub vault_authority: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [b"share_mint", vault.key().as_ref(), asset_mint.key().as_ref()], bump)]
+    pub share_mint: Account<'info, Mint>,
 
-#[derive(Accounts)]
-pub struct InitializeFactory<'info> {
     #[account(
-        init, 
-        seeds = [b"vault_factory"], 
-        bump, 
-        payer = owner, 
-        space = 8 + 32 + 4 + 4 + (32 * 1000) + 4 + (32 * 1000)
+        mut,
+        seeds = [b"withdrawal_escrow", vault.key().as_ref(), asset_mint.key().as_ref()],
+        bump
     )]
-    pub factory: Account<'info, Factory>,
-    #[account(mut)]
-    pub owner: Signer<'info>,
-    pub system_program: Program<'info, System>,
-}
+    pub escrow_share_account: Account<'info, TokenAccount>,
 
-#[derive(Accounts)]
-#[instruction(manager: Pubkey)]
-pub struct CreateVault<'info> {
-    #[account(mut, seeds = [b"vault_factory"], bump)]
-    pub factory: Account<'info, Factory>,
-    #[account(mut)] 
-    pub owner: Signer<'info>,
-    #[account(init, seeds = [b"vault", manager.as_ref()], bump, payer = owner, space = 8 + 32 + 8 + 4)]
-    pub vault: Account<'info, Vault>,
-    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
 }
 
 #[derive(Accounts)]
-pub struct DepositSol<'info> {
+pub struct ClaimWithdrawalSol<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
 
+    #[account(seeds = [b"vault_factory"], bump)]
+    pub factory: Account<'info, Factory>,
+
+    #[account(mut, seeds = [b"vault", vault.creator.as_ref(), &vault.nonce.to_le_bytes()], bump)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(mut, seeds = [b"depositor", vault.key().as_ref(), user.key().as_ref()], bump)]
+    pub depositor: Account<'info, Depositor>,
+
     #[account(
-        init_if_needed,
-        payer = user,
-        space = 8 + 32 + 1 + 8 + 8 + 8 + 32,
-        seeds = [b"depositor", user.key().as_ref()],
+        mut,
+        close = user,
+        seeds = [b"withdrawal_request", vault.key().as_ref(), token::spl_token::native_mint::ID.as_ref(), user.key().as_ref()],
         bump
     )]
-    pub depositor: Account<'info, Depositor>,
-    
-    #[account(mut, seeds = [b"vault_pda"], bump)]
-    /// CHECK: PDA for holding SOL
-    pub vault_pda: AccountInfo<'info>,
+    pub withdrawal_request: Account<'info, WithdrawalRequest>,
+
+    #[account(
+        seeds = [b"withdrawal_epoch", vault.key().as_ref(), token::spl_token::native_mint::ID.as_ref(), &withdrawal_request.epoch.to_le_bytes()],
+        bump
+    )]
+    pub withdrawal_epoch: Account<'info, WithdrawalEpoch>,
+
+    #[account(mut, seeds = [b"vault_sol", vault.key().as_ref()], bump)]
+    pub vault_sol_account: SystemAccount<'info>,
 
-    #[account(mut, seeds = [b"vault", vault.manager.as_ref()], bump)]
-    pub vault: Account<'info, Vault>,
-    
     pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct DepositUsdc<'info> {
+pub struct ClaimWithdrawalToken<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
 
+    #[account(seeds = [b"vault_factory"], bump)]
+    pub factory: Account<'info, Factory>,
+
+    #[account(mut, seeds = [b"vault", vault.creator.as_ref(), &vault.nonce.to_le_bytes()], bump)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(mut, seeds = [b"depositor", vault.key().as_ref(), user.key().as_ref()], bump)]
+    pub depositor: Account<'info, Depositor>,
+
     #[account(
-        init_if_needed,
-        payer = user,
-        space = 8 + 32 + 1 + 8 + 8 + 8 + 32,
-        seeds = [b"depositor", user.key().as_ref()],
+        mut,
+        close = user,
+        seeds = [b"withdrawal_request", vault.key().as_ref(), token_mint.key().as_ref(), user.key().as_ref()],
         bump
     )]
-    pub depositor: Account<'info, Depositor>,
+    pub withdrawal_request: Account<'info, WithdrawalRequest>,
 
     #[account(
-        init_if_needed,
-        payer = user,
-        token::mint = usdc_mint,
-        token::authority = vault_usdc_account,
-        seeds = [b"vault_usdc_account"],
+        seeds = [b"withdrawal_epoch", vault.key().as_ref(), token_mint.key().as_ref(), &withdrawal_request.epoch.to_le_bytes()],
         bump
     )]
-    pub vault_usdc_account: Account<'info, TokenAccount>,
+    pub withdrawal_epoch: Account<'info, WithdrawalEpoch>,
 
-    #[account(mut, token::mint = usdc_mint)]
-    pub user_usdc_account: Account<'info, TokenAccount>,
+    /// CHECK: PDA signing for this vault's token accounts, holds no data
+    #[account(seeds = [b"vault_authority", vault.key().as_ref()], bump)]
+    pub vault_authority: UncheckedAccount<'info>,
 
-    pub usdc_mint: Account<'info, Mint>,
-    pub token_program: Program<'info, Token>,
-    pub system_program: Program<'info, System>,
+    #[account(
+        mut,
+        token::mint = token_mint,
+        token::authority = vault_authority,
+        token::token_program = asset_token_program,
+        seeds = [b"vault_token", vault.key().as_ref()],
+        bump
+    )]
+    pub vault_token_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, token::mint = token_mint, token::token_program = asset_token_program)]
+    pub user_token_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mint::token_program = asset_token_program)]
+    pub token_mint: InterfaceAccount<'info, token_interface::Mint>,
+    pub asset_token_program: Interface<'info, TokenInterface>,
 }
 
 #[derive(Accounts)]
-pub struct Withdraw<'info> {
-    #[account(mut)]
-    pub user: Signer<'info>,
+pub struct ConvertShares<'info> {
+    #[account(seeds = [b"vault", vault.creator.as_ref(), &vault.nonce.to_le_bytes()], bump)]
+    pub vault: Account<'info, Vault>,
+    /// CHECK: only used to pick the pool; the native mint means SOL
+    pub asset_mint: UncheckedAccount<'info>,
+    #[account(seeds = [b"share_mint", vault.key().as_ref(), asset_mint.key().as_ref()], bump)]
+    pub share_mint: Account<'info, Mint>,
+    /// CHECK: checked against the pool's custody PDA in `pool_assets`
+    pub custody: UncheckedAccount<'info>,
+}
 
-    #[account(mut, seeds = [b"depositor", user.key().as_ref()], bump)]
+#[derive(Accounts)]
+#[instruction(user: Pubkey)]
+pub struct PositionAge<'info> {
+    #[account(seeds = [b"vault", vault.creator.as_ref(), &vault.nonce.to_le_bytes()], bump)]
+    pub vault: Account<'info, Vault>,
+    #[account(seeds = [b"depositor", vault.key().as_ref(), user.as_ref()], bump)]
     pub depositor: Account<'info, Depositor>,
+}
 
-    #[account(mut, seeds = [b"vault_pda"], bump)]
-    /// CHECK: Vault PDA holding SOL
-    pub vault_pda: AccountInfo<'info>,
-
-    #[account(mut, token::mint = usdc_mint, seeds = [b"vault_usdc_account"], bump)]
-    pub vault_usdc_account: Account<'info, TokenAccount>,
-
-    #[account(mut, token::mint = usdc_mint)]
-    pub user_usdc_account: Account<'info, TokenAccount>,
+#[derive(Accounts)]
+#[instruction(manager: Pubkey)]
+pub struct ListManagerVaults<'info> {
+    #[account(seeds = [b"manager_profile", manager.as_ref()], bump)]
+    pub manager_profile: Account<'info, ManagerProfile>,
+}
 
-    pub usdc_mint: Account<'info, Mint>,
-    pub token_program: Program<'info, Token>,
+#[derive(Accounts)]
+#[instruction(user: Pubkey)]
+pub struct ListPositions<'info> {
+    #[account(seeds = [b"user_positions", user.as_ref()], bump)]
+    pub user_positions: Account<'info, UserPositions>,
 }
 
 #[account]
 pub struct Factory {
     pub owner: Pubkey,
     pub vault_count: u32,
+    pub page_count: u32,
+    pub max_management_fee_bps: u16,
+    pub max_performance_fee_bps: u16,
+    pub deposits_paused: bool,
+    pub withdrawals_paused: bool,
+    pub pending_owner: Option<Pubkey>,
+}
+
+#[account]
+pub struct VaultRegistryPage {
+    pub factory: Pubkey,
+    pub index: u32,
+    pub next_page: Option<Pubkey>,
     pub vaults: Vec<Pubkey>,
-    pub vault_managers: Vec<Pubkey>,
 }
 
 #[account]
@@ -270,7 +2824,108 @@ pub struct Vault {
     pub manager: Pubkey,
     pub total_deposit: u64,
     pub index: u32,
-    pub vault: Pubkey
+    pub vault: Pubkey,
+    pub total_token_deposit: u64,
+    pub management_fee_bps: u16,
+    pub performance_fee_bps: u16,
+    pub sol_fees: FeeState,
+    pub token_fees: FeeState,
+    pub deposits_paused: bool,
+    pub withdrawals_paused: bool,
+    pub caps: DepositCaps,
+    pub allowlist_root: Option<[u8; 32]>,
+    pub token_mint: Pubkey,
+    pub lockup: Lockup,
+    pub sol_queue: WithdrawalQueue,
+    pub token_queue: WithdrawalQueue,
+    pub creator: Pubkey,                  // manager the address was derived from; never changes
+    pub pending_manager: Option<Pubkey>,
+    pub nonce: u32,                       // creator's vault number, the other address seed
+    pub status: VaultStatus,
+}
+
+#[account]
+pub struct MintRegistry {
+    pub mints: Vec<AcceptedMint>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct AcceptedMint {
+    pub mint: Pubkey,
+    pub decimals: u8,
+    pub enabled: bool,
+    pub deposit_cap: u64, // across every vault; 0 = uncapped
+    pub total_deposited: u64,
+}
+
+// Zero in any field leaves that limit off
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct DepositCaps {
+    pub sol_total: u64,
+    pub token_total: u64,
+    pub sol_per_depositor: u64,
+    pub token_per_depositor: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct DepositCapacity {
+    pub sol: u64,
+    pub token: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct Lockup {
+    pub lock_period: i64,            // seconds each deposit stays locked; 0 = no lock
+    pub early_exit_penalty_bps: u16, // 0 = early withdrawals are refused
+    pub penalty_destination: PenaltyDestination,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PenaltyDestination {
+    #[default]
+    Depositors,
+    Treasury,
+}
+
+// Closing is one-way: deposits are refused until the manager closes the vault
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VaultStatus {
+    #[default]
+    Active,
+    Closing,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct WithdrawalQueue {
+    pub epoch: u64,           // epoch currently taking requests
+    pub reserved_assets: u64, // settled but not yet claimed
+}
+
+#[account]
+pub struct WithdrawalEpoch {
+    pub vault: Pubkey,
+    pub asset_mint: Pubkey,
+    pub epoch: u64,
+    pub shares: u64,
+    pub assets: u64, // set at settlement
+    pub settled: bool,
+}
+
+#[account]
+pub struct WithdrawalRequest {
+    pub owner: Pubkey,
+    pub vault: Pubkey,
+    pub asset_mint: Pubkey,
+    pub epoch: u64,
+    pub shares: u64,
+    pub lots: Vec<Lot>, // deposit times of the escrowed shares, oldest first
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct FeeState {
+    pub last_accrual: i64,
+    pub high_water_mark: u64,
+    pub pending_shares: u64,
 }
 
 #[account]
@@ -279,8 +2934,303 @@ pub struct Depositor {
     pub is_initialized: bool,
     pub deposit_time: i64,
     pub sol_amount: u64,
-    pub usdc_amount: u64,
-    pub vault_pda: Pubkey,
+    pub token_amount: u64,
+    pub vault: Pubkey,
+    pub verified_root: [u8; 32],
+    pub sol_lots: Vec<Lot>,   // oldest first
+    pub token_lots: Vec<Lot>, // oldest first
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct Lot {
+    pub shares: u64,
+    pub deposit_time: i64,
+}
+
+// Share-weighted age of each pool position in seconds
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct PositionAges {
+    pub sol: i64,
+    pub token: i64,
+}
+
+// Vaults a manager currently runs, and the nonce for the next one they are given
+#[account]
+pub struct ManagerProfile {
+    pub manager: Pubkey,
+    pub next_nonce: u32,
+    pub vaults: Vec<Pubkey>,
+}
+
+#[account]
+pub struct UserPositions {
+    pub owner: Pubkey,
+    pub vaults: Vec<Pubkey>,
+}
+
+#[event]
+pub struct FactoryInitialized {
+    pub factory: Pubkey,
+    pub owner: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct FactoryPauseUpdated {
+    pub factory: Pubkey,
+    pub owner: Pubkey,
+    pub deposits_paused: bool,
+    pub withdrawals_paused: bool,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct VaultPauseUpdated {
+    pub vault: Pubkey,
+    pub authority: Pubkey,
+    pub deposits_paused: bool,
+    pub withdrawals_paused: bool,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct FeeLimitsUpdated {
+    pub factory: Pubkey,
+    pub owner: Pubkey,
+    pub max_management_fee_bps: u16,
+    pub max_performance_fee_bps: u16,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct MintRegistryInitialized {
+    pub mint_registry: Pubkey,
+    pub owner: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct MintConfigured {
+    pub owner: Pubkey,
+    pub mint: Pubkey,
+    pub enabled: bool,
+    pub deposit_cap: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct VaultCreated {
+    pub factory: Pubkey,
+    pub vault: Pubkey,
+    pub manager: Pubkey,
+    pub nonce: u32,
+    pub index: u32,
+    pub token_mint: Pubkey,
+    pub registry_page: Pubkey,
+    pub creator: Pubkey,
+    pub timestamp: i64,
+}
+
+// `asset_mint` is the native mint for the SOL pool
+#[event]
+pub struct Deposited {
+    pub vault: Pubkey,
+    pub user: Pubkey,
+    pub asset_mint: Pubkey,
+    pub requested: u64,
+    pub received: u64,             // measured increase of the vault custody
+    pub shares: u64,
+    pub depositor_principal: u64,  // afterwards
+    pub total_principal: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct Withdrawn {
+    pub vault: Pubkey,
+    pub user: Pubkey,
+    pub asset_mint: Pubkey,
+    pub shares: u64,               // burned, including the penalty
+    pub penalty_shares: u64,
+    pub amount: u64,
+    pub depositor_principal: u64,
+    pub total_principal: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct LockupUpdated {
+    pub vault: Pubkey,
+    pub manager: Pubkey,
+    pub lockup: Lockup,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AllowlistRootUpdated {
+    pub vault: Pubkey,
+    pub manager: Pubkey,
+    pub root: Option<[u8; 32]>,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct DepositCapsUpdated {
+    pub vault: Pubkey,
+    pub authority: Pubkey,
+    pub caps: DepositCaps,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct VaultFeesUpdated {
+    pub vault: Pubkey,
+    pub manager: Pubkey,
+    pub management_fee_bps: u16,
+    pub performance_fee_bps: u16,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct FeesAccrued {
+    pub vault: Pubkey,
+    pub asset_mint: Pubkey,
+    pub total_assets: u64,
+    pub fee_shares: u64,
+    pub pending_shares: u64,
+    pub high_water_mark: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct FeesClaimed {
+    pub vault: Pubkey,
+    pub manager: Pubkey,
+    pub asset_mint: Pubkey,
+    pub shares: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct WithdrawalRequested {
+    pub vault: Pubkey,
+    pub user: Pubkey,
+    pub asset_mint: Pubkey,
+    pub epoch: u64,
+    pub shares: u64,
+    pub penalty_shares: u64,
+    pub request_shares: u64,       // escrowed for this request afterwards
+    pub epoch_shares: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct WithdrawalCancelled {
+    pub vault: Pubkey,
+    pub user: Pubkey,
+    pub asset_mint: Pubkey,
+    pub epoch: u64,
+    pub shares: u64,
+    pub epoch_shares: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct WithdrawalsSettled {
+    pub vault: Pubkey,
+    pub asset_mint: Pubkey,
+    pub epoch: u64,
+    pub shares: u64,
+    pub assets: u64,
+    pub next_epoch: u64,
+    pub reserved_assets: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct WithdrawalClaimed {
+    pub vault: Pubkey,
+    pub user: Pubkey,
+    pub asset_mint: Pubkey,
+    pub epoch: u64,
+    pub shares: u64,
+    pub amount: u64,
+    pub depositor_principal: u64,
+    pub reserved_assets: u64,
+    pub timestamp: i64,
+}
+
+// Shared by the factory owner and vault manager handovers; `account` is the factory or the vault
+#[event]
+pub struct AuthorityProposed {
+    pub account: Pubkey,
+    pub authority: Pubkey,
+    pub pending: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AuthorityTransferred {
+    pub account: Pubkey,
+    pub previous: Pubkey,
+    pub authority: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AuthorityTransferCancelled {
+    pub account: Pubkey,
+    pub authority: Pubkey,
+    pub pending: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ManagerRotated {
+    pub vault: Pubkey,
+    pub authority: Pubkey,
+    pub previous: Pubkey,
+    pub manager: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AuthorityRenounced {
+    pub account: Pubkey,
+    pub previous: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct VaultClosing {
+    pub vault: Pubkey,
+    pub authority: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct VaultClosed {
+    pub vault: Pubkey,
+    pub manager: Pubkey,
+    pub sol_swept: u64,
+    pub token_swept: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PositionClosed {
+    pub vault: Pubkey,
+    pub user: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PositionSynced {
+    pub vault: Pubkey,
+    pub user: Pubkey,
+    pub asset_mint: Pubkey,
+    pub shares: u64,
+    pub timestamp: i64,
 }
 
 #[error_code]
@@ -303,4 +3253,56 @@ pub enum CustomError {
     InvalidMint,
     #[msg("Insufficient balance")]
     InsufficientBalance,
+    #[msg("Previous registry page required")]
+    MissingPreviousPage,
+    #[msg("Registry page full")]
+    RegistryPageFull,
+    #[msg("Position belongs to a different vault")]
+    VaultMismatch,
+    #[msg("Too many positions for this wallet")]
+    TooManyPositions,
+    #[msg("Custody account does not belong to this vault")]
+    InvalidCustody,
+    #[msg("Fee exceeds the allowed maximum")]
+    FeeTooHigh,
+    #[msg("Paused")]
+    Paused,
+    #[msg("Deposit cap exceeded")]
+    DepositCapExceeded,
+    #[msg("Depositor is not on the allowlist")]
+    NotAllowlisted,
+    #[msg("Mint is disabled")]
+    MintDisabled,
+    #[msg("Too many accepted mints")]
+    TooManyMints,
+    #[msg("Mint uses an unsupported Token-2022 extension")]
+    UnsupportedMintExtension,
+    #[msg("Deposit is still locked")]
+    Locked,
+    #[msg("Invalid lockup configuration")]
+    InvalidLockup,
+    #[msg("Claim the earlier withdrawal request first")]
+    WithdrawalPending,
+    #[msg("Withdrawal epoch already settled")]
+    WithdrawalSettled,
+    #[msg("Withdrawal epoch not settled yet")]
+    WithdrawalNotSettled,
+    #[msg("Invalid authority")]
+    InvalidAuthority,
+    #[msg("No authority transfer is pending")]
+    NoPendingAuthority,
+    #[msg("Too many vaults for this manager")]
+    TooManyManagedVaults,
+    #[msg("Vault is closing")]
+    VaultClosing,
+    #[msg("Vault is not closing")]
+    VaultNotClosing,
+    #[msg("Vault still owes depositors")]
+    OutstandingLiabilities,
+    #[msg("Token account required for the remaining custody balance")]
+    TokenAccountRequired,
+    #[msg("Previous manager profile required")]
+    ManagerProfileRequired,
+    #[msg("The native mint cannot be a vault's token")]
+    NativeMintNotAllowed,
 }
\ No newline at end of file
@@ -1,21 +1,284 @@
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::bpf_loader_upgradeable;
+use anchor_lang::Discriminator;
+#[cfg(feature = "fixed-owner")]
+use std::str::FromStr;
 
 
 declare_id!("HDhkebca19sS5qcas1DXkCQJoxN6upiEvc8wYZvFp4y7");
 
+pub const BPS_DENOMINATOR: u64 = 10_000;
+pub const REWARD_SCALE: u128 = 1_000_000_000_000;
+pub const MAX_LOTS: usize = 16;
+pub const SWEEP_TIMELOCK: i64 = 3 * 24 * 60 * 60;
+// Liabilities `close_vault` tolerates once every position is gone: floor rounding in shared
+// penalties leaves a lamport or so per settlement behind that nobody can withdraw
+pub const CLOSE_DUST_LIMIT: u64 = 10_000;
+
+pub const VAULT_SPACE: usize = 8 + 32 + 1 + 1 + 8 + 8 + 8 + (8 + 2 + 1) + 16 + 8 + 8 + (1 + 32) + 1 + 4;
+pub const DEPOSITOR_SPACE: usize = 8 + 32 + 1 + 8 + 8 + 16 + 4 + (8 + 8) * MAX_LOTS; // discriminator + owner pubkey + is_initialized (bool) + amount + deposit_time + reward_debt + lots
+// Layouts deployed before the vault settings and lots existed: discriminator + owner, and
+// discriminator + owner + is_initialized + amount + deposit_time
+pub const LEGACY_VAULT_SPACE: usize = 8 + 32;
+pub const LEGACY_DEPOSITOR_SPACE: usize = 8 + 32 + 1 + 8 + 8;
+
 #[program]
 pub mod vault {
     use super::*;
 
     pub fn initialize(ctx: Context<Initialize>) -> Result<()> {
+        require_deployer(&ctx.accounts.owner.key(), &ctx.accounts.program_data)?;
+
         let vault = &mut ctx.accounts.vault;
         vault.owner = *ctx.accounts.owner.key;
+        vault.deposits_paused = false;
+        vault.withdrawals_paused = false;
+        vault.deposit_cap = 0;
+        vault.depositor_cap = 0;
+        vault.total_liabilities = 0;
+        vault.lockup = Lockup::default();
+        vault.reward_index = 0;
+        vault.sweep_amount = 0;
+        vault.sweep_unlock_time = 0;
+        vault.pending_owner = None;
+        vault.status = VaultStatus::Active;
+        vault.open_positions = 0;
+
+        emit!(VaultInitialized {
+            vault: vault.key(),
+            owner: vault.owner,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // Grows the deployed `[b"vault"]` account to the current layout. Every appended field starts at
+    // its zeroed default, except that whatever custody already holds is booked as owed to the
+    // depositors who put it there; anything beyond their claims can only leave through a sweep.
+    pub fn migrate_vault(ctx: Context<MigrateVault>) -> Result<()> {
+        let info = ctx.accounts.vault.to_account_info();
+        let owner = legacy_owner(&info, VaultAccount::DISCRIMINATOR, LEGACY_VAULT_SPACE)?;
+        require_keys_eq!(owner, ctx.accounts.owner.key(), CustomError::Unauthorized);
+
+        grow_legacy_account(&info, &ctx.accounts.owner.to_account_info(), &ctx.accounts.system_program.to_account_info(), VAULT_SPACE)?;
+
+        let custody = ctx.accounts.vault_pda.to_account_info();
+        let mut vault = VaultAccount::try_deserialize(&mut &info.data.borrow()[..])?;
+        vault.total_liabilities = custody.lamports().saturating_sub(Rent::get()?.minimum_balance(custody.data_len()));
+        vault.try_serialize(&mut &mut info.data.borrow_mut()[..])?;
+
+        emit!(VaultMigrated {
+            vault: info.key(),
+            owner,
+            total_liabilities: vault.total_liabilities,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // Grows a deployed position to the current layout. Its balance is already part of the
+    // liabilities booked by `migrate_vault`; it earns shared penalties from now on, and having been
+    // deposited before lockups existed it carries no lock.
+    pub fn migrate_depositor(ctx: Context<MigrateDepositor>) -> Result<()> {
+        let info = ctx.accounts.depositor.to_account_info();
+        let owner = legacy_owner(&info, Depositor::DISCRIMINATOR, LEGACY_DEPOSITOR_SPACE)?;
+        require_keys_eq!(owner, ctx.accounts.user.key(), CustomError::Unauthorized);
+
+        grow_legacy_account(&info, &ctx.accounts.user.to_account_info(), &ctx.accounts.system_program.to_account_info(), DEPOSITOR_SPACE)?;
+
+        let vault = &mut ctx.accounts.vault;
+        let mut depositor = Depositor::try_deserialize(&mut &info.data.borrow()[..])?;
+        depositor.reward_debt = accrued_rewards(depositor.amount, vault.reward_index)?;
+        depositor.try_serialize(&mut &mut info.data.borrow_mut()[..])?;
+        vault.open_positions = vault.open_positions.checked_add(1).unwrap();
+
+        emit!(DepositorMigrated {
+            vault: vault.key(),
+            user: owner,
+            amount: depositor.amount,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn migrate_custody(ctx: Context<MigrateCustody>) -> Result<()> {
+        let vault = &ctx.accounts.vault;
+        require_keys_eq!(vault.owner, ctx.accounts.owner.key(), CustomError::Unauthorized);
+
+        // `init` finds the adopted lamports already rent-exempt, so the rent for the data it adds
+        // would come out of the liabilities booked by `migrate_vault`; the owner tops it up instead
+        let top_up = vault.total_liabilities.saturating_sub(custody_balance(&ctx.accounts.vault_pda)?);
+        if top_up > 0 {
+            let ix = anchor_lang::solana_program::system_instruction::transfer(
+                &ctx.accounts.owner.key(),
+                &ctx.accounts.vault_pda.key(),
+                top_up,
+            );
+            anchor_lang::solana_program::program::invoke(
+                &ix,
+                &[
+                    ctx.accounts.owner.to_account_info(),
+                    ctx.accounts.vault_pda.to_account_info(),
+                ],
+            )?;
+        }
+
+        emit!(CustodyMigrated {
+            vault: vault.key(),
+            owner: vault.owner,
+            vault_balance: ctx.accounts.vault_pda.get_lamports(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // Ownership moves only once the proposed key signs `accept_authority`
+    pub fn propose_authority(ctx: Context<UpdateAuthority>, new_owner: Pubkey) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        require_keys_eq!(vault.owner, ctx.accounts.authority.key(), CustomError::Unauthorized);
+        require_keys_neq!(new_owner, Pubkey::default(), CustomError::InvalidAuthority);
+
+        vault.pending_owner = Some(new_owner);
+
+        emit!(AuthorityProposed {
+            vault: vault.key(),
+            owner: vault.owner,
+            pending_owner: new_owner,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn accept_authority(ctx: Context<UpdateAuthority>) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        let pending_owner = vault.pending_owner.ok_or(CustomError::NoPendingAuthority)?;
+        require_keys_eq!(pending_owner, ctx.accounts.authority.key(), CustomError::Unauthorized);
+
+        let previous_owner = vault.owner;
+        vault.owner = pending_owner;
+        vault.pending_owner = None;
+
+        emit!(AuthorityTransferred {
+            vault: vault.key(),
+            previous_owner,
+            owner: vault.owner,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn cancel_authority_transfer(ctx: Context<UpdateAuthority>) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        require_keys_eq!(vault.owner, ctx.accounts.authority.key(), CustomError::Unauthorized);
+        let pending_owner = vault.pending_owner.ok_or(CustomError::NoPendingAuthority)?;
+
+        vault.pending_owner = None;
+
+        emit!(AuthorityTransferCancelled {
+            vault: vault.key(),
+            owner: vault.owner,
+            pending_owner,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // Leaves the vault ownerless for good; depositors can still deposit and withdraw
+    pub fn renounce_authority(ctx: Context<UpdateAuthority>) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        require_keys_eq!(vault.owner, ctx.accounts.authority.key(), CustomError::Unauthorized);
+
+        let previous_owner = vault.owner;
+        vault.owner = Pubkey::default();
+        vault.pending_owner = None;
+
+        emit!(AuthorityRenounced {
+            vault: vault.key(),
+            previous_owner,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn set_lockup(ctx: Context<SetLockup>, lockup: Lockup) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+
+        // Only the vault owner can change the lock; a zero penalty refuses early withdrawals outright
+        require_keys_eq!(vault.owner, ctx.accounts.owner.key(), CustomError::Unauthorized);
+        require!(lockup.lock_period >= 0, CustomError::InvalidLockup);
+        require!(lockup.early_exit_penalty_bps as u64 <= BPS_DENOMINATOR, CustomError::InvalidLockup);
+
+        vault.lockup = lockup;
+
+        emit!(LockupUpdated {
+            vault: vault.key(),
+            owner: vault.owner,
+            lockup,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn set_deposit_caps(ctx: Context<SetDepositCaps>, deposit_cap: u64, depositor_cap: u64) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+
+        // Only the vault owner can change caps; zero leaves a cap off
+        require_keys_eq!(vault.owner, ctx.accounts.owner.key(), CustomError::Unauthorized);
+
+        vault.deposit_cap = deposit_cap;
+        vault.depositor_cap = depositor_cap;
+
+        emit!(DepositCapsUpdated {
+            vault: vault.key(),
+            owner: vault.owner,
+            deposit_cap,
+            depositor_cap,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn remaining_capacity(ctx: Context<RemainingCapacity>, _user: Pubkey) -> Result<u64> {
+        let vault = &ctx.accounts.vault;
+        let deposited = ctx.accounts.depositor.as_ref().map_or(0, |depositor| depositor.amount);
+
+        Ok(remaining_under_cap(vault.deposit_cap, vault.total_liabilities)
+            .min(remaining_under_cap(vault.depositor_cap, deposited)))
+    }
+
+    pub fn position_age(ctx: Context<PositionAge>, _user: Pubkey) -> Result<i64> {
+        weighted_age(&ctx.accounts.depositor.lots, Clock::get()?.unix_timestamp)
+    }
+
+    pub fn set_pause(ctx: Context<SetPause>, deposits_paused: bool, withdrawals_paused: bool) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+
+        // Only the vault owner can pause or unpause
+        require_keys_eq!(vault.owner, ctx.accounts.owner.key(), CustomError::Unauthorized);
+
+        vault.deposits_paused = deposits_paused;
+        vault.withdrawals_paused = withdrawals_paused;
+
+        emit!(PauseUpdated {
+            vault: vault.key(),
+            owner: vault.owner,
+            deposits_paused,
+            withdrawals_paused,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
         Ok(())
     }
 
     pub fn deposit(ctx: Context<Deposit>, amount: u64) -> Result<()> {
+        require!(!ctx.accounts.vault.deposits_paused, CustomError::Paused);
+        require!(ctx.accounts.vault.status == VaultStatus::Active, CustomError::VaultClosing);
+        let vault = &mut ctx.accounts.vault;
         let depositor = &mut ctx.accounts.depositor;
 
+        // Guard: reject deposits past the vault-wide or per-depositor cap
+        require!(amount <= remaining_under_cap(vault.deposit_cap, vault.total_liabilities), CustomError::DepositCapExceeded);
+        require!(amount <= remaining_under_cap(vault.depositor_cap, depositor.amount), CustomError::DepositCapExceeded);
+
         // Guard: check signer matches depositor.owner pubkey if already initialized
         if depositor.is_initialized {
             require_keys_eq!(depositor.owner, ctx.accounts.user.key(), CustomError::Unauthorized);
@@ -23,52 +286,493 @@ pub mod vault {
             // First-time init: set owner and mark initialized
             depositor.owner = *ctx.accounts.user.key;
             depositor.is_initialized = true;
+            vault.open_positions = vault.open_positions.checked_add(1).unwrap();
         }
 
+        let now = Clock::get()?.unix_timestamp;
+        depositor.settle_rewards(vault.reward_index)?;
+
         // Transfer SOL from user to vault PDA
-        **ctx.accounts.user.try_borrow_mut_lamports()? -= amount;
-        **ctx.accounts.vault_pda.to_account_info().try_borrow_mut_lamports()? += amount;
+        let ix = anchor_lang::solana_program::system_instruction::transfer(
+            &ctx.accounts.user.key(),
+            &ctx.accounts.vault_pda.key(),
+            amount,
+        );
+        anchor_lang::solana_program::program::invoke(
+            &ix,
+            &[
+                ctx.accounts.user.to_account_info(),
+                ctx.accounts.vault_pda.to_account_info(),
+            ],
+        )?;
 
         depositor.amount = depositor.amount.checked_add(amount).unwrap();
-        depositor.deposit_time = Clock::get()?.unix_timestamp;
+        depositor.reward_debt = accrued_rewards(depositor.amount, vault.reward_index)?;
+        depositor.deposit_time = now;
+        add_lot(&mut depositor.lots, amount, now);
+        vault.total_liabilities = vault.total_liabilities.checked_add(amount).unwrap();
 
+        emit!(Deposited {
+            vault: vault.key(),
+            user: depositor.owner,
+            amount,
+            balance: depositor.amount,
+            total_liabilities: vault.total_liabilities,
+            timestamp: now,
+        });
         Ok(())
     }
 
-    pub fn withdraw(ctx: Context<Withdraw>) -> Result<()> {
+    pub fn withdraw(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
+        require!(!ctx.accounts.vault.withdrawals_paused, CustomError::Paused);
+        require!(amount > 0, CustomError::InvalidAmount);
+        let vault = &mut ctx.accounts.vault;
+        let depositor = &mut ctx.accounts.depositor;
+
+        // Guard: ensure only owner can withdraw
+        require_keys_eq!(depositor.owner, ctx.accounts.user.key(), CustomError::Unauthorized);
+
+        let now = Clock::get()?.unix_timestamp;
+        let penalty = debit(vault, depositor, amount, now)?;
+        let payout = amount - penalty;
+        require!(payout <= custody_balance(&ctx.accounts.vault_pda)?, CustomError::InsufficientBalance);
+
+        // Pay the signer unless another wallet was named
+        let recipient = match &ctx.accounts.recipient {
+            Some(recipient) => recipient.to_account_info(),
+            None => ctx.accounts.user.to_account_info(),
+        };
+        **ctx.accounts.vault_pda.to_account_info().try_borrow_mut_lamports()? -= payout;
+        **recipient.try_borrow_mut_lamports()? += payout;
+
+        emit!(Withdrawn {
+            vault: vault.key(),
+            user: depositor.owner,
+            recipient: recipient.key(),
+            amount,
+            penalty,
+            balance: depositor.amount,
+            total_liabilities: vault.total_liabilities,
+            reward_index: vault.reward_index,
+            timestamp: now,
+        });
+        Ok(())
+    }
+
+    // Withdraws the whole balance and closes the position, refunding its rent to the signer
+    pub fn withdraw_all_and_close(ctx: Context<WithdrawAllAndClose>) -> Result<()> {
+        require!(!ctx.accounts.vault.withdrawals_paused, CustomError::Paused);
+        let vault = &mut ctx.accounts.vault;
         let depositor = &mut ctx.accounts.depositor;
 
         // Guard: ensure only owner can withdraw
         require_keys_eq!(depositor.owner, ctx.accounts.user.key(), CustomError::Unauthorized);
 
+        let now = Clock::get()?.unix_timestamp;
+        depositor.settle_rewards(vault.reward_index)?;
         let amount = depositor.amount;
+        let penalty = debit(vault, depositor, amount, now)?;
+        let payout = amount - penalty;
+        require!(payout <= custody_balance(&ctx.accounts.vault_pda)?, CustomError::InsufficientBalance);
 
-        **ctx.accounts.vault_pda.to_account_info().try_borrow_mut_lamports()? -= amount;
-        **ctx.accounts.user.try_borrow_mut_lamports()? += amount;
+        let recipient = match &ctx.accounts.recipient {
+            Some(recipient) => recipient.to_account_info(),
+            None => ctx.accounts.user.to_account_info(),
+        };
+        **ctx.accounts.vault_pda.to_account_info().try_borrow_mut_lamports()? -= payout;
+        **recipient.try_borrow_mut_lamports()? += payout;
+
+        emit!(Withdrawn {
+            vault: vault.key(),
+            user: depositor.owner,
+            recipient: recipient.key(),
+            amount,
+            penalty,
+            balance: depositor.amount,
+            total_liabilities: vault.total_liabilities,
+            reward_index: vault.reward_index,
+            timestamp: now,
+        });
+        vault.open_positions = vault.open_positions.saturating_sub(1);
+        emit!(DepositorClosed {
+            vault: vault.key(),
+            user: depositor.owner,
+            timestamp: now,
+        });
+        Ok(())
+    }
+
+    // Anyone may clear out a position with nothing left in it; the rent goes back to its owner.
+    // Lets a closing vault get rid of positions their owners left behind empty.
+    pub fn close_empty_position(ctx: Context<CloseEmptyPosition>) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        let depositor = &mut ctx.accounts.depositor;
+
+        depositor.settle_rewards(vault.reward_index)?;
+        require!(depositor.amount == 0, CustomError::PositionNotEmpty);
 
-        depositor.amount = 0;
+        vault.open_positions = vault.open_positions.saturating_sub(1);
+        emit!(DepositorClosed {
+            vault: vault.key(),
+            user: depositor.owner,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
         Ok(())
     }
 
     pub fn owner_withdraw(ctx: Context<OwnerWithdraw>, amount: u64) -> Result<()> {
         let vault = &ctx.accounts.vault;
 
-        // Only the vault owner can withdraw
+        // Only the vault owner can withdraw, and only lamports no depositor has a claim on
         require_keys_eq!(vault.owner, ctx.accounts.owner.key(), CustomError::Unauthorized);
+        let surplus = custody_balance(&ctx.accounts.vault_pda)?.saturating_sub(vault.total_liabilities);
+        require!(amount <= surplus, CustomError::InsufficientSurplus);
 
         **ctx.accounts.vault_pda.to_account_info().try_borrow_mut_lamports()? -= amount;
         **ctx.accounts.owner.try_borrow_mut_lamports()? += amount;
 
+        emit!(OwnerWithdrawn {
+            vault: vault.key(),
+            owner: vault.owner,
+            amount,
+            vault_balance: ctx.accounts.vault_pda.get_lamports(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
         Ok(())
     }
+
+    // Emergency path past the surplus limit: the sweep is announced first and can only run
+    // SWEEP_TIMELOCK later, giving depositors time to withdraw
+    pub fn announce_sweep(ctx: Context<ManageSweep>, amount: u64) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        require_keys_eq!(vault.owner, ctx.accounts.owner.key(), CustomError::Unauthorized);
+        require!(amount > 0, CustomError::InvalidAmount);
+
+        let now = Clock::get()?.unix_timestamp;
+        vault.sweep_amount = amount;
+        vault.sweep_unlock_time = now.checked_add(SWEEP_TIMELOCK).unwrap();
+
+        emit!(SweepAnnounced {
+            vault: vault.key(),
+            owner: vault.owner,
+            amount,
+            unlock_time: vault.sweep_unlock_time,
+            timestamp: now,
+        });
+        Ok(())
+    }
+
+    pub fn cancel_sweep(ctx: Context<ManageSweep>) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        require_keys_eq!(vault.owner, ctx.accounts.owner.key(), CustomError::Unauthorized);
+        require!(vault.sweep_amount > 0, CustomError::NoSweepPending);
+
+        let amount = vault.sweep_amount;
+        vault.sweep_amount = 0;
+        vault.sweep_unlock_time = 0;
+
+        emit!(SweepCancelled {
+            vault: vault.key(),
+            owner: vault.owner,
+            amount,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn execute_sweep(ctx: Context<ExecuteSweep>) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        require_keys_eq!(vault.owner, ctx.accounts.owner.key(), CustomError::Unauthorized);
+        require!(vault.sweep_amount > 0, CustomError::NoSweepPending);
+
+        let now = Clock::get()?.unix_timestamp;
+        require!(now >= vault.sweep_unlock_time, CustomError::SweepTimelocked);
+
+        // The PDA itself stays rent-exempt; whatever depositors left behind is all that can go
+        let available = custody_balance(&ctx.accounts.vault_pda)?;
+        let amount = vault.sweep_amount.min(available);
+
+        **ctx.accounts.vault_pda.to_account_info().try_borrow_mut_lamports()? -= amount;
+        **ctx.accounts.owner.try_borrow_mut_lamports()? += amount;
+
+        vault.sweep_amount = 0;
+        vault.sweep_unlock_time = 0;
+
+        emit!(SweepExecuted {
+            vault: vault.key(),
+            owner: vault.owner,
+            amount,
+            total_liabilities: vault.total_liabilities,
+            vault_balance: ctx.accounts.vault_pda.get_lamports(),
+            timestamp: now,
+        });
+        Ok(())
+    }
+
+    // Deposits stop for good; withdrawals keep working and lock penalties no longer apply
+    pub fn begin_close(ctx: Context<BeginClose>) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        require_keys_eq!(vault.owner, ctx.accounts.owner.key(), CustomError::Unauthorized);
+        require!(vault.status == VaultStatus::Active, CustomError::VaultClosing);
+
+        vault.status = VaultStatus::Closing;
+
+        emit!(VaultClosing {
+            vault: vault.key(),
+            owner: vault.owner,
+            total_liabilities: vault.total_liabilities,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // Once every position is closed and what is still owed is down to rounding dust, custody and
+    // the vault go back to the owner with their rent. Positions alone are not enough: a migrated
+    // vault books its legacy deposits before any of their positions have been migrated.
+    pub fn close_vault(ctx: Context<CloseVault>) -> Result<()> {
+        let vault = &ctx.accounts.vault;
+        require_keys_eq!(vault.owner, ctx.accounts.owner.key(), CustomError::Unauthorized);
+        require!(vault.status == VaultStatus::Closing, CustomError::VaultNotClosing);
+        require!(vault.open_positions == 0, CustomError::OutstandingLiabilities);
+        require!(vault.total_liabilities <= CLOSE_DUST_LIMIT, CustomError::OutstandingLiabilities);
+
+        emit!(VaultClosed {
+            vault: vault.key(),
+            owner: vault.owner,
+            reclaimed: ctx.accounts.vault_pda.get_lamports() + vault.get_lamports(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+}
+
+// Singletons may only be created by the deployer: the upgrade authority recorded in this program's
+// ProgramData, or with the `fixed-owner` feature the key baked in through INITIAL_OWNER at build time
+fn require_deployer(signer: &Pubkey, program_data: &Option<Account<ProgramData>>) -> Result<()> {
+    #[cfg(feature = "fixed-owner")]
+    {
+        let _ = program_data;
+        let initial_owner = Pubkey::from_str(env!("INITIAL_OWNER")).map_err(|_| error!(CustomError::Unauthorized))?;
+        require_keys_eq!(*signer, initial_owner, CustomError::Unauthorized);
+    }
+
+    #[cfg(not(feature = "fixed-owner"))]
+    {
+        let program_data = program_data.as_ref().ok_or(error!(CustomError::Unauthorized))?;
+        require!(program_data.upgrade_authority_address == Some(*signer), CustomError::Unauthorized);
+    }
+    Ok(())
+}
+
+// Owner of an account still in its deployed layout, which is this program's account of the given
+// type at exactly `legacy_space` bytes
+fn legacy_owner(info: &AccountInfo, discriminator: [u8; 8], legacy_space: usize) -> Result<Pubkey> {
+    require_keys_eq!(*info.owner, crate::ID, CustomError::NotLegacyAccount);
+    require!(info.data_len() == legacy_space, CustomError::NotLegacyAccount);
+
+    let data = info.try_borrow_data()?;
+    require!(data[..8] == discriminator, CustomError::NotLegacyAccount);
+    Ok(Pubkey::try_from(&data[8..40]).unwrap())
+}
+
+// Zeroed bytes decode as the default of every appended field; the payer tops up the rent
+fn grow_legacy_account<'info>(
+    info: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    space: usize,
+) -> Result<()> {
+    let top_up = Rent::get()?.minimum_balance(space).saturating_sub(info.lamports());
+    if top_up > 0 {
+        let ix = anchor_lang::solana_program::system_instruction::transfer(payer.key, info.key, top_up);
+        anchor_lang::solana_program::program::invoke(&ix, &[payer.clone(), info.clone(), system_program.clone()])?;
+    }
+    info.realloc(space, true)?;
+    Ok(())
+}
+
+// Lamports the custody can pay out while staying rent-exempt
+fn custody_balance(custody: &Account<VaultCustody>) -> Result<u64> {
+    let info = custody.to_account_info();
+    Ok(info.lamports().saturating_sub(Rent::get()?.minimum_balance(info.data_len())))
+}
+
+// A cap of zero means no cap
+fn remaining_under_cap(cap: u64, used: u64) -> u64 {
+    if cap == 0 {
+        u64::MAX
+    } else {
+        cap.saturating_sub(used)
+    }
+}
+
+// Share of penalties a balance has earned at the given reward index
+fn accrued_rewards(amount: u64, reward_index: u128) -> Result<u128> {
+    Ok((amount as u128).checked_mul(reward_index).unwrap() / REWARD_SCALE)
+}
+
+// Takes `amount` off a position after crediting its shared penalties and returns the early-exit
+// penalty withheld from the payout
+fn debit(vault: &mut VaultAccount, depositor: &mut Depositor, amount: u64, now: i64) -> Result<u64> {
+    depositor.settle_rewards(vault.reward_index)?;
+    require!(amount <= depositor.amount, CustomError::InsufficientBalance);
+
+    let locked = consume_lots(&mut depositor.lots, amount, vault.lockup.lock_period, now);
+    // A closing vault lets everyone out without penalty
+    let penalty = match vault.status {
+        VaultStatus::Active => early_exit_penalty(&vault.lockup, locked)?,
+        VaultStatus::Closing => 0,
+    };
+
+    depositor.amount -= amount;
+    depositor.reward_debt = accrued_rewards(depositor.amount, vault.reward_index)?;
+    vault.total_liabilities = vault.total_liabilities.saturating_sub(amount);
+
+    // Penalties shared with depositors raise everyone's balance; otherwise (or with nobody left) they stay behind as owner surplus
+    if vault.lockup.penalty_destination == PenaltyDestination::Depositors && vault.total_liabilities > 0 {
+        let per_deposit = (penalty as u128) * REWARD_SCALE / (vault.total_liabilities as u128);
+        vault.reward_index = vault.reward_index.checked_add(per_deposit).unwrap();
+        vault.total_liabilities = vault.total_liabilities.checked_add(penalty).unwrap();
+    }
+    Ok(penalty)
+}
+
+// Withdrawing still-locked lamports is refused, or costs the configured penalty on that part
+fn early_exit_penalty(lockup: &Lockup, locked: u64) -> Result<u64> {
+    if locked == 0 {
+        return Ok(0);
+    }
+
+    require!(lockup.early_exit_penalty_bps > 0, CustomError::Locked);
+    Ok(((locked as u128) * (lockup.early_exit_penalty_bps as u128) / (BPS_DENOMINATOR as u128)) as u64)
+}
+
+// Each deposit is its own lot; once the list is full the two oldest are folded together,
+// keeping the later timestamp so nothing unlocks early
+fn add_lot(lots: &mut Vec<Lot>, amount: u64, now: i64) {
+    lots.push(Lot { amount, deposit_time: now });
+    if lots.len() > MAX_LOTS {
+        let oldest = lots.remove(0);
+        lots[0].amount = lots[0].amount.saturating_add(oldest.amount);
+        lots[0].deposit_time = lots[0].deposit_time.max(oldest.deposit_time);
+    }
+}
+
+// Take `amount` out of the lots oldest first and report how much of it was still locked
+fn consume_lots(lots: &mut Vec<Lot>, amount: u64, lock_period: i64, now: i64) -> u64 {
+    let mut remaining = amount;
+    let mut locked = 0u64;
+    while remaining > 0 && !lots.is_empty() {
+        let taken = remaining.min(lots[0].amount);
+        if now < lots[0].deposit_time.saturating_add(lock_period) {
+            locked += taken;
+        }
+        lots[0].amount -= taken;
+        remaining -= taken;
+        if lots[0].amount == 0 {
+            lots.remove(0);
+        }
+    }
+    locked
+}
+
+// Amount-weighted age of the lots in seconds
+fn weighted_age(lots: &[Lot], now: i64) -> Result<i64> {
+    let mut total: u128 = 0;
+    let mut weighted: u128 = 0;
+    for lot in lots {
+        let age = now.saturating_sub(lot.deposit_time).max(0) as u128;
+        total += lot.amount as u128;
+        weighted = weighted.checked_add((lot.amount as u128).checked_mul(age).unwrap()).unwrap();
+    }
+
+    if total == 0 {
+        return Ok(0);
+    }
+    Ok((weighted / total) as i64)
 }
 
 #[derive(Accounts)]
 pub struct Initialize<'info> {
-    #[account(init, payer = owner, space = 8 + 32, seeds = [b"vault"], bump)]
+    #[account(init, payer = owner, space = VAULT_SPACE, seeds = [b"vault"], bump)]
+    pub vault: Account<'info, VaultAccount>,
+    #[account(init, payer = owner, space = 8, seeds = [b"vault_pda"], bump)]
+    pub vault_pda: Account<'info, VaultCustody>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    /// This program's ProgramData; may be omitted when built with `fixed-owner`
+    #[account(seeds = [crate::ID.as_ref()], bump, seeds::program = bpf_loader_upgradeable::ID)]
+    pub program_data: Option<Account<'info, ProgramData>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateVault<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    /// CHECK: still in its deployed layout; checked in `legacy_owner`
+    #[account(
+        mut,
+        seeds = [b"vault"],
+        bump
+    )]
+    pub vault: UncheckedAccount<'info>,
+
+    /// CHECK: custody in either its legacy system-owned or its program-owned form; only read
+    #[account(
+        seeds = [b"vault_pda"],
+        bump
+    )]
+    pub vault_pda: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateDepositor<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"vault"],
+        bump
+    )]
     pub vault: Account<'info, VaultAccount>,
+
+    /// CHECK: still in its deployed layout; checked in `legacy_owner`
+    #[account(
+        mut,
+        seeds = [b"depositor", user.key().as_ref()],
+        bump
+    )]
+    pub depositor: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+// Adopts the lamports already sitting at `[b"vault_pda"]` from before custody was program-owned
+#[derive(Accounts)]
+pub struct MigrateCustody<'info> {
     #[account(mut)]
     pub owner: Signer<'info>,
+
+    #[account(
+        seeds = [b"vault"],
+        bump
+    )]
+    pub vault: Account<'info, VaultAccount>,
+
+    #[account(
+        init,
+        payer = owner,
+        space = 8,
+        seeds = [b"vault_pda"],
+        bump
+    )]
+    pub vault_pda: Account<'info, VaultCustody>,
+
     pub system_program: Program<'info, System>,
 }
 
@@ -77,10 +781,17 @@ pub struct Deposit<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
 
+    #[account(
+        mut,
+        seeds = [b"vault"],
+        bump
+    )]
+    pub vault: Account<'info, VaultAccount>,
+
     #[account(
         init_if_needed,
         payer = user,
-        space = 8 + 32 + 1 + 8 + 8, // discriminator + owner pubkey + is_initialized (bool) + amount + deposit_time
+        space = DEPOSITOR_SPACE,
         seeds = [b"depositor", user.key().as_ref()],
         bump
     )]
@@ -91,8 +802,7 @@ pub struct Deposit<'info> {
         seeds = [b"vault_pda"],
         bump
     )]
-    /// CHECK: PDA used to store lamports
-    pub vault_pda: AccountInfo<'info>,
+    pub vault_pda: Account<'info, VaultCustody>,
 
     pub system_program: Program<'info, System>,
 }
@@ -102,6 +812,13 @@ pub struct Withdraw<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
 
+    #[account(
+        mut,
+        seeds = [b"vault"],
+        bump
+    )]
+    pub vault: Account<'info, VaultAccount>,
+
     #[account(
         mut,
         seeds = [b"depositor", user.key().as_ref()],
@@ -114,8 +831,140 @@ pub struct Withdraw<'info> {
         seeds = [b"vault_pda"],
         bump
     )]
-    /// CHECK: PDA used to store lamports
-    pub vault_pda: AccountInfo<'info>,
+    pub vault_pda: Account<'info, VaultCustody>,
+
+    // Wallet to pay instead of the signer
+    #[account(mut)]
+    pub recipient: Option<SystemAccount<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawAllAndClose<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"vault"],
+        bump
+    )]
+    pub vault: Account<'info, VaultAccount>,
+
+    #[account(
+        mut,
+        close = user,
+        seeds = [b"depositor", user.key().as_ref()],
+        bump
+    )]
+    pub depositor: Account<'info, Depositor>,
+
+    #[account(
+        mut,
+        seeds = [b"vault_pda"],
+        bump
+    )]
+    pub vault_pda: Account<'info, VaultCustody>,
+
+    // Wallet to pay instead of the signer; the position's rent always goes back to the signer
+    #[account(mut)]
+    pub recipient: Option<SystemAccount<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct CloseEmptyPosition<'info> {
+    /// CHECK: receives the position's rent; tied to it by the depositor seeds
+    #[account(mut)]
+    pub owner: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"vault"],
+        bump
+    )]
+    pub vault: Account<'info, VaultAccount>,
+
+    #[account(
+        mut,
+        close = owner,
+        seeds = [b"depositor", owner.key().as_ref()],
+        bump
+    )]
+    pub depositor: Account<'info, Depositor>,
+}
+
+#[derive(Accounts)]
+pub struct SetPause<'info> {
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"vault"],
+        bump
+    )]
+    pub vault: Account<'info, VaultAccount>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateAuthority<'info> {
+    // The owner, or the pending owner when accepting
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"vault"],
+        bump
+    )]
+    pub vault: Account<'info, VaultAccount>,
+}
+
+#[derive(Accounts)]
+pub struct SetLockup<'info> {
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"vault"],
+        bump
+    )]
+    pub vault: Account<'info, VaultAccount>,
+}
+
+#[derive(Accounts)]
+pub struct SetDepositCaps<'info> {
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"vault"],
+        bump
+    )]
+    pub vault: Account<'info, VaultAccount>,
+}
+
+#[derive(Accounts)]
+#[instruction(user: Pubkey)]
+pub struct RemainingCapacity<'info> {
+    #[account(
+        seeds = [b"vault"],
+        bump
+    )]
+    pub vault: Account<'info, VaultAccount>,
+
+    #[account(
+        seeds = [b"depositor", user.as_ref()],
+        bump
+    )]
+    pub depositor: Option<Account<'info, Depositor>>,
+}
+
+#[derive(Accounts)]
+#[instruction(user: Pubkey)]
+pub struct PositionAge<'info> {
+    #[account(
+        seeds = [b"depositor", user.as_ref()],
+        bump
+    )]
+    pub depositor: Account<'info, Depositor>,
 }
 
 #[derive(Accounts)]
@@ -134,13 +983,116 @@ pub struct OwnerWithdraw<'info> {
         seeds = [b"vault_pda"],
         bump
     )]
-    /// CHECK: PDA used to store lamports
-    pub vault_pda: AccountInfo<'info>,
+    pub vault_pda: Account<'info, VaultCustody>,
+}
+
+#[derive(Accounts)]
+pub struct ManageSweep<'info> {
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"vault"],
+        bump
+    )]
+    pub vault: Account<'info, VaultAccount>,
+}
+
+#[derive(Accounts)]
+pub struct BeginClose<'info> {
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"vault"],
+        bump
+    )]
+    pub vault: Account<'info, VaultAccount>,
+}
+
+#[derive(Accounts)]
+pub struct CloseVault<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut,
+        close = owner,
+        seeds = [b"vault"],
+        bump
+    )]
+    pub vault: Account<'info, VaultAccount>,
+
+    #[account(
+        mut,
+        close = owner,
+        seeds = [b"vault_pda"],
+        bump
+    )]
+    pub vault_pda: Account<'info, VaultCustody>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteSweep<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"vault"],
+        bump
+    )]
+    pub vault: Account<'info, VaultAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"vault_pda"],
+        bump
+    )]
+    pub vault_pda: Account<'info, VaultCustody>,
 }
 
+// Program-owned lamport custody; the discriminator is its only data
+#[account]
+pub struct VaultCustody {}
+
 #[account]
 pub struct VaultAccount {
     pub owner: Pubkey,
+    pub deposits_paused: bool,
+    pub withdrawals_paused: bool,
+    pub deposit_cap: u64,     // 0 = uncapped
+    pub depositor_cap: u64,   // 0 = uncapped
+    pub total_liabilities: u64, // lamports owed to depositors, shared penalties included
+    pub lockup: Lockup,
+    pub reward_index: u128,   // penalties shared per deposited lamport, scaled by REWARD_SCALE
+    pub sweep_amount: u64,    // announced emergency sweep; 0 = none pending
+    pub sweep_unlock_time: i64,
+    pub pending_owner: Option<Pubkey>,
+    pub status: VaultStatus,
+    pub open_positions: u32,  // depositor accounts still open
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct Lockup {
+    pub lock_period: i64,            // seconds each deposit stays locked; 0 = no lock
+    pub early_exit_penalty_bps: u16, // 0 = early withdrawals are refused
+    pub penalty_destination: PenaltyDestination,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PenaltyDestination {
+    #[default]
+    Depositors,
+    Treasury,
+}
+
+// Closing is one-way: deposits are refused until the owner closes the vault
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VaultStatus {
+    #[default]
+    Active,
+    Closing,
 }
 
 #[account]
@@ -149,10 +1101,235 @@ pub struct Depositor {
     pub is_initialized: bool,
     pub amount: u64,
     pub deposit_time: i64,
+    pub reward_debt: u128,
+    pub lots: Vec<Lot>,      // oldest first
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct Lot {
+    pub amount: u64,
+    pub deposit_time: i64,
+}
+
+impl Depositor {
+    // Credit penalties shared out since this balance last changed
+    pub fn settle_rewards(&mut self, reward_index: u128) -> Result<()> {
+        let pending = accrued_rewards(self.amount, reward_index)?.saturating_sub(self.reward_debt);
+        self.amount = self.amount.checked_add(pending as u64).unwrap();
+
+        // Shared penalties are not deposits, so they join the oldest lot instead of starting a new lock
+        if let Some(oldest) = self.lots.first_mut() {
+            oldest.amount = oldest.amount.checked_add(pending as u64).unwrap();
+        }
+        self.reward_debt = accrued_rewards(self.amount, reward_index)?;
+        Ok(())
+    }
+}
+
+#[event]
+pub struct VaultInitialized {
+    pub vault: Pubkey,
+    pub owner: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct LockupUpdated {
+    pub vault: Pubkey,
+    pub owner: Pubkey,
+    pub lockup: Lockup,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct DepositCapsUpdated {
+    pub vault: Pubkey,
+    pub owner: Pubkey,
+    pub deposit_cap: u64,
+    pub depositor_cap: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PauseUpdated {
+    pub vault: Pubkey,
+    pub owner: Pubkey,
+    pub deposits_paused: bool,
+    pub withdrawals_paused: bool,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct Deposited {
+    pub vault: Pubkey,
+    pub user: Pubkey,
+    pub amount: u64,
+    pub balance: u64,         // depositor balance afterwards
+    pub total_liabilities: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct Withdrawn {
+    pub vault: Pubkey,
+    pub user: Pubkey,
+    pub recipient: Pubkey,
+    pub amount: u64,          // debited from the balance; `amount - penalty` was paid out
+    pub penalty: u64,
+    pub balance: u64,
+    pub total_liabilities: u64,
+    pub reward_index: u128,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct VaultMigrated {
+    pub vault: Pubkey,
+    pub owner: Pubkey,
+    pub total_liabilities: u64, // custody balance booked as owed
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct DepositorMigrated {
+    pub vault: Pubkey,
+    pub user: Pubkey,
+    pub amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct CustodyMigrated {
+    pub vault: Pubkey,
+    pub owner: Pubkey,
+    pub vault_balance: u64,   // lamports adopted, rent top-up included
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct DepositorClosed {
+    pub vault: Pubkey,
+    pub user: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AuthorityProposed {
+    pub vault: Pubkey,
+    pub owner: Pubkey,
+    pub pending_owner: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AuthorityTransferred {
+    pub vault: Pubkey,
+    pub previous_owner: Pubkey,
+    pub owner: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AuthorityTransferCancelled {
+    pub vault: Pubkey,
+    pub owner: Pubkey,
+    pub pending_owner: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AuthorityRenounced {
+    pub vault: Pubkey,
+    pub previous_owner: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct OwnerWithdrawn {
+    pub vault: Pubkey,
+    pub owner: Pubkey,
+    pub amount: u64,
+    pub vault_balance: u64,   // lamports left in the vault PDA
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SweepAnnounced {
+    pub vault: Pubkey,
+    pub owner: Pubkey,
+    pub amount: u64,
+    pub unlock_time: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SweepCancelled {
+    pub vault: Pubkey,
+    pub owner: Pubkey,
+    pub amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SweepExecuted {
+    pub vault: Pubkey,
+    pub owner: Pubkey,
+    pub amount: u64,
+    pub total_liabilities: u64,
+    pub vault_balance: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct VaultClosing {
+    pub vault: Pubkey,
+    pub owner: Pubkey,
+    pub total_liabilities: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct VaultClosed {
+    pub vault: Pubkey,
+    pub owner: Pubkey,
+    pub reclaimed: u64,       // lamports returned from custody and the vault account
+    pub timestamp: i64,
 }
 
 #[error_code]
 pub enum CustomError {
     #[msg("Unauthorized action")]
     Unauthorized,
+    #[msg("Vault is paused")]
+    Paused,
+    #[msg("Deposit cap exceeded")]
+    DepositCapExceeded,
+    #[msg("Deposit is still locked")]
+    Locked,
+    #[msg("Invalid lockup configuration")]
+    InvalidLockup,
+    #[msg("Amount exceeds the vault surplus above depositor liabilities")]
+    InsufficientSurplus,
+    #[msg("Invalid amount")]
+    InvalidAmount,
+    #[msg("Insufficient balance")]
+    InsufficientBalance,
+    #[msg("No sweep is pending")]
+    NoSweepPending,
+    #[msg("Sweep is still timelocked")]
+    SweepTimelocked,
+    #[msg("Invalid authority")]
+    InvalidAuthority,
+    #[msg("No authority transfer is pending")]
+    NoPendingAuthority,
+    #[msg("Vault is closing")]
+    VaultClosing,
+    #[msg("Vault is not closing")]
+    VaultNotClosing,
+    #[msg("Depositor positions are still open or lamports are still owed")]
+    OutstandingLiabilities,
+    #[msg("Position still holds a balance")]
+    PositionNotEmpty,
+    #[msg("Account is not in its deployed layout")]
+    NotLegacyAccount,
 }
@@ -1,16 +1,169 @@
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, TokenAccount, Token, Transfer, Mint};
+use anchor_lang::solana_program::bpf_loader_upgradeable;
+use anchor_lang::Discriminator;
+use anchor_spl::token_2022::spl_token_2022::extension::{BaseStateWithExtensions, ExtensionType, StateWithExtensions};
+use anchor_spl::token_2022::spl_token_2022::instruction::AuthorityType;
+use anchor_spl::token_interface::{self, CloseAccount, Mint, SetAuthority, TokenAccount, TokenInterface, TransferChecked};
+#[cfg(feature = "fixed-owner")]
 use std::str::FromStr;
 
 declare_id!("2vo1Sdq39gUPV1GoivRXz8t7tqsCcaa8WiQ3AeZhHynE");
 
-const USDC_MINT: &str = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v"; // Correct mainnet USDC mint
+pub const MAX_ACCEPTED_MINTS: usize = 8;
+pub const BPS_DENOMINATOR: u64 = 10_000;
+pub const REWARD_SCALE: u128 = 1_000_000_000_000;
+pub const MAX_LOTS: usize = 16;
+
+pub const DEPOSITOR_SPACE: usize = 8 + 32 + 1 + 8 + 8 + 32 + 8 + 16 + 16 + (4 + (8 + 8) * MAX_LOTS) * 2;
+// Depositors deployed before reward debts and lots: discriminator + owner + is_initialized + two amounts + mint + deposit_time
+pub const LEGACY_DEPOSITOR_SPACE: usize = 8 + 32 + 1 + 8 + 8 + 32 + 8;
 
 #[program]
 pub mod vault_version2{
     use super::*;
 
+    pub fn initialize_config(ctx: Context<InitializeConfig>) -> Result<()> {
+        require_deployer(&ctx.accounts.admin.key(), &ctx.accounts.program_data)?;
+
+        let config = &mut ctx.accounts.config;
+        config.admin = ctx.accounts.admin.key();
+        config.deposits_paused = false;
+        config.withdrawals_paused = false;
+        config.caps = DepositCaps::default();
+        // SOL deposited before the config existed is booked as owed to the depositors who put it there
+        let custody = ctx.accounts.vault_pda.to_account_info();
+        config.total_sol = custody.lamports().saturating_sub(Rent::get()?.minimum_balance(custody.data_len()));
+        config.accepted_mints = Vec::new();
+        config.lockup = Lockup::default();
+        config.sol_reward_index = 0;
+        config.withdrawal_epoch = 0;
+        config.queued_sol = 0;
+        config.authority_bump = ctx.bumps.vault_authority;
+
+        emit!(ConfigInitialized {
+            config: config.key(),
+            admin: config.admin,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn set_lockup(ctx: Context<SetLockup>, lockup: Lockup) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        require_keys_eq!(config.admin, ctx.accounts.admin.key(), CustomError::Unauthorized);
+        require!(lockup.lock_period >= 0, CustomError::InvalidLockup);
+        require!(lockup.early_exit_penalty_bps as u64 <= BPS_DENOMINATOR, CustomError::InvalidLockup);
+
+        config.lockup = lockup;
+
+        emit!(LockupUpdated {
+            admin: config.admin,
+            lockup,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn configure_mint(ctx: Context<ConfigureMint>, enabled: bool, deposit_cap: u64) -> Result<()> {
+        let mint = &ctx.accounts.mint;
+        let config = &mut ctx.accounts.config;
+        require_keys_eq!(config.admin, ctx.accounts.admin.key(), CustomError::Unauthorized);
+        require_supported_mint(&mint.to_account_info())?;
+
+        match config.accepted_mints.iter_mut().find(|entry| entry.mint == mint.key()) {
+            Some(entry) => {
+                entry.enabled = enabled;
+                entry.deposit_cap = deposit_cap;
+            }
+            None => {
+                require!(config.accepted_mints.len() < MAX_ACCEPTED_MINTS, CustomError::TooManyMints);
+                config.accepted_mints.push(AcceptedMint {
+                    mint: mint.key(),
+                    decimals: mint.decimals,
+                    enabled,
+                    deposit_cap,
+                    total_deposited: 0,
+                    reward_index: 0,
+                    queued: 0,
+                });
+            }
+        }
+
+        emit!(MintConfigured {
+            admin: config.admin,
+            mint: mint.key(),
+            enabled,
+            deposit_cap,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn set_deposit_caps(ctx: Context<SetDepositCaps>, caps: DepositCaps) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        require_keys_eq!(config.admin, ctx.accounts.admin.key(), CustomError::Unauthorized);
+
+        config.caps = caps;
+
+        emit!(DepositCapsUpdated {
+            admin: config.admin,
+            caps,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn remaining_capacity(ctx: Context<RemainingCapacity>, _user: Pubkey, mint: Pubkey) -> Result<DepositCapacity> {
+        let config = &ctx.accounts.config;
+        let (sol_amount, token_amount, token_mint) = match &ctx.accounts.depositor {
+            Some(depositor) => (depositor.sol_amount, depositor.token_amount, depositor.token_mint),
+            None => (0, 0, mint),
+        };
+
+        // A depositor holds one token at a time, so other mints are closed to them until it is withdrawn
+        let token = match config.accepted_mints.iter().find(|entry| entry.mint == mint) {
+            Some(entry) if entry.enabled && (token_amount == 0 || token_mint == mint) => {
+                remaining_under_cap(entry.deposit_cap, entry.total_deposited)
+                    .min(remaining_under_cap(config.caps.token_per_depositor, token_amount))
+            }
+            _ => 0,
+        };
+
+        Ok(DepositCapacity {
+            sol: remaining_under_cap(config.caps.sol_total, config.total_sol)
+                .min(remaining_under_cap(config.caps.sol_per_depositor, sol_amount)),
+            token,
+        })
+    }
+
+    pub fn position_age(ctx: Context<PositionAge>, _user: Pubkey) -> Result<PositionAges> {
+        let depositor = &ctx.accounts.depositor;
+        let now = Clock::get()?.unix_timestamp;
+
+        Ok(PositionAges {
+            sol: weighted_age(&depositor.sol_lots, now)?,
+            token: weighted_age(&depositor.token_lots, now)?,
+        })
+    }
+
+    pub fn set_pause(ctx: Context<SetPause>, deposits_paused: bool, withdrawals_paused: bool) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        require_keys_eq!(config.admin, ctx.accounts.admin.key(), CustomError::Unauthorized);
+
+        config.deposits_paused = deposits_paused;
+        config.withdrawals_paused = withdrawals_paused;
+
+        emit!(PauseUpdated {
+            admin: config.admin,
+            deposits_paused,
+            withdrawals_paused,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
     pub fn deposit_sol(ctx: Context<DepositSol>, amount: u64) -> Result<()> {
+        require!(!ctx.accounts.config.deposits_paused, CustomError::Paused);
         let depositor = &mut ctx.accounts.depositor;
 
         if depositor.is_initialized {
@@ -18,11 +171,19 @@ pub mod vault_version2{
         } else {
             depositor.owner = *ctx.accounts.user.key;
             depositor.is_initialized = true;
-            depositor.usdc_mint = Pubkey::from_str(USDC_MINT).unwrap();
         }
 
+        let config = &mut ctx.accounts.config;
+        require!(amount <= remaining_under_cap(config.caps.sol_total, config.total_sol), CustomError::DepositCapExceeded);
+        require!(amount <= remaining_under_cap(config.caps.sol_per_depositor, depositor.sol_amount), CustomError::DepositCapExceeded);
+
+        depositor.settle_sol_rewards(config.sol_reward_index)?;
         depositor.sol_amount = depositor.sol_amount.checked_add(amount).ok_or(CustomError::MathOverflow)?;
-        depositor.deposit_time = Clock::get()?.unix_timestamp;
+        depositor.sol_reward_debt = accrued_rewards(depositor.sol_amount, config.sol_reward_index)?;
+        let now = Clock::get()?.unix_timestamp;
+        depositor.deposit_time = now;
+        add_lot(&mut depositor.sol_lots, amount, now);
+        config.total_sol = config.total_sol.checked_add(amount).ok_or(CustomError::MathOverflow)?;
 
         // Use system program transfer instead of manual lamport manipulation
         let ix = anchor_lang::solana_program::system_instruction::transfer(
@@ -38,10 +199,18 @@ pub mod vault_version2{
             ],
         )?;
 
+        emit!(SolDeposited {
+            user: depositor.owner,
+            amount,
+            balance: depositor.sol_amount,
+            total_sol: config.total_sol,
+            timestamp: now,
+        });
         Ok(())
     }
 
-    pub fn deposit_usdc(ctx: Context<DepositUsdc>, amount: u64) -> Result<()> {
+    pub fn deposit_token(ctx: Context<DepositToken>, amount: u64) -> Result<()> {
+        require!(!ctx.accounts.config.deposits_paused, CustomError::Paused);
         let depositor = &mut ctx.accounts.depositor;
 
         if depositor.is_initialized {
@@ -49,37 +218,82 @@ pub mod vault_version2{
         } else {
             depositor.owner = *ctx.accounts.user.key;
             depositor.is_initialized = true;
-            depositor.usdc_mint = Pubkey::from_str(USDC_MINT).unwrap();
         }
 
-        // Validate mint against the actual USDC mint account
-        require_keys_eq!(ctx.accounts.usdc_mint.key(), Pubkey::from_str(USDC_MINT).unwrap(), CustomError::InvalidMint);
-        require_keys_eq!(ctx.accounts.user_usdc_account.mint, ctx.accounts.usdc_mint.key(), CustomError::InvalidMint);
-        require_keys_eq!(ctx.accounts.vault_usdc_account.mint, ctx.accounts.usdc_mint.key(), CustomError::InvalidMint);
+        // Validate mint against the accepted-mint registry
+        let mint = ctx.accounts.token_mint.key();
+        require_keys_eq!(ctx.accounts.user_token_account.mint, mint, CustomError::InvalidMint);
+        require_keys_eq!(ctx.accounts.vault_token_account.mint, mint, CustomError::InvalidMint);
+
+        require_supported_mint(&ctx.accounts.token_mint.to_account_info())?;
 
-        // Transfer USDC
-        let cpi_accounts = Transfer {
-            from: ctx.accounts.user_usdc_account.to_account_info(),
-            to: ctx.accounts.vault_usdc_account.to_account_info(),
+        // A depositor holds one token at a time; the mint is free to change once the balance is zero
+        if depositor.token_amount == 0 {
+            depositor.token_mint = mint;
+            depositor.token_lots.clear();
+        }
+        require_keys_eq!(depositor.token_mint, mint, CustomError::InvalidMint);
+
+        // Transfer tokens
+        let balance_before = ctx.accounts.vault_token_account.amount;
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.user_token_account.to_account_info(),
+            mint: ctx.accounts.token_mint.to_account_info(),
+            to: ctx.accounts.vault_token_account.to_account_info(),
             authority: ctx.accounts.user.to_account_info(),
         };
         let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
-        token::transfer(cpi_ctx, amount)?;
+        token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.token_mint.decimals)?;
+
+        // Only the measured increase is credited, whatever the mint charged on the way in
+        ctx.accounts.vault_token_account.reload()?;
+        let received = ctx.accounts.vault_token_account.amount
+            .checked_sub(balance_before)
+            .ok_or(CustomError::MathOverflow)?;
 
-        depositor.usdc_amount = depositor.usdc_amount.checked_add(amount).ok_or(CustomError::MathOverflow)?;
-        depositor.deposit_time = Clock::get()?.unix_timestamp;
+        let config = &mut ctx.accounts.config;
+        require!(received <= remaining_under_cap(config.caps.token_per_depositor, depositor.token_amount), CustomError::DepositCapExceeded);
 
+        let entry = config.accepted_mints.iter_mut().find(|entry| entry.mint == mint).ok_or(CustomError::InvalidMint)?;
+        require!(entry.enabled, CustomError::MintDisabled);
+        require!(entry.decimals == ctx.accounts.token_mint.decimals, CustomError::InvalidMint);
+        require!(received <= remaining_under_cap(entry.deposit_cap, entry.total_deposited), CustomError::DepositCapExceeded);
+        entry.total_deposited = entry.total_deposited.checked_add(received).ok_or(CustomError::MathOverflow)?;
+
+        depositor.settle_token_rewards(entry.reward_index)?;
+        depositor.token_amount = depositor.token_amount.checked_add(received).ok_or(CustomError::MathOverflow)?;
+        depositor.token_reward_debt = accrued_rewards(depositor.token_amount, entry.reward_index)?;
+        let now = Clock::get()?.unix_timestamp;
+        depositor.deposit_time = now;
+        add_lot(&mut depositor.token_lots, received, now);
+
+        emit!(TokenDeposited {
+            user: depositor.owner,
+            mint,
+            requested: amount,
+            received,
+            balance: depositor.token_amount,
+            total_deposited: entry.total_deposited,
+            timestamp: now,
+        });
         Ok(())
     }
 
-    pub fn withdraw(ctx: Context<Withdraw>, sol_amount: u64, usdc_amount: u64) -> Result<()> {
-        let depositor = &mut ctx.accounts.depositor;
+    pub fn withdraw(ctx: Context<Withdraw>, sol_amount: u64, token_amount: u64) -> Result<()> {
+        require!(!ctx.accounts.config.withdrawals_paused, CustomError::Paused);
+        let config_key = ctx.accounts.config.key();
+        let config: &mut VaultConfig = &mut ctx.accounts.config;
+        let depositor: &mut Depositor = &mut ctx.accounts.depositor;
         require_keys_eq!(depositor.owner, ctx.accounts.user.key(), CustomError::Unauthorized);
 
+        let mut sol_paid = 0;
+        let mut token_paid = 0;
+
         // Withdraw SOL
         if sol_amount > 0 {
-            require!(depositor.sol_amount >= sol_amount, CustomError::InsufficientBalance);
-            
+            let (payout, _) = debit_sol(config, depositor, sol_amount)?;
+            sol_paid = payout;
+
             let bump = ctx.bumps.vault_pda;
             let seeds = &[b"vault_pda".as_ref(), &[bump]];
             let signer = &[&seeds[..]];
@@ -87,7 +301,7 @@ pub mod vault_version2{
             let ix = anchor_lang::solana_program::system_instruction::transfer(
                 &ctx.accounts.vault_pda.key(),
                 &ctx.accounts.user.key(),
-                sol_amount,
+                payout,
             );
             anchor_lang::solana_program::program::invoke_signed(
                 &ix,
@@ -97,37 +311,684 @@ pub mod vault_version2{
                 ],
                 signer,
             )?;
+        }
+
+        // Withdraw tokens
+        if token_amount > 0 {
+            // Validate mint; disabled mints can still be withdrawn
+            require_keys_eq!(ctx.accounts.token_mint.key(), depositor.token_mint, CustomError::InvalidMint);
+            require_keys_eq!(ctx.accounts.user_token_account.mint, ctx.accounts.token_mint.key(), CustomError::InvalidMint);
+            require_keys_eq!(ctx.accounts.vault_token_account.mint, ctx.accounts.token_mint.key(), CustomError::InvalidMint);
+
+            let (payout, _) = debit_token(config, depositor, &ctx.accounts.token_mint.key(), token_amount)?;
+            token_paid = payout;
+
+            let seeds = &[b"vault_authority".as_ref(), config_key.as_ref(), &[config.authority_bump]];
+            let signer = &[&seeds[..]];
+
+            let cpi_accounts = TransferChecked {
+                from: ctx.accounts.vault_token_account.to_account_info(),
+                mint: ctx.accounts.token_mint.to_account_info(),
+                to: ctx.accounts.user_token_account.to_account_info(),
+                authority: ctx.accounts.vault_authority.to_account_info(),
+            };
+
+            let cpi_ctx = CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), cpi_accounts, signer);
+            token_interface::transfer_checked(cpi_ctx, payout, ctx.accounts.token_mint.decimals)?;
+        }
+
+        emit!(Withdrawn {
+            user: depositor.owner,
+            sol_amount,
+            sol_paid,
+            token_mint: ctx.accounts.token_mint.key(),
+            token_amount,
+            token_paid,
+            sol_balance: depositor.sol_amount,
+            token_balance: depositor.token_amount,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // Moves amounts out of the depositor's balance into the open withdrawal epoch. Lock penalties
+    // are taken now; the rest is paid by `claim_withdrawal` once the admin settles the epoch.
+    pub fn request_withdraw(ctx: Context<RequestWithdraw>, sol_amount: u64, token_amount: u64) -> Result<()> {
+        require!(!ctx.accounts.config.withdrawals_paused, CustomError::Paused);
+        let config: &mut VaultConfig = &mut ctx.accounts.config;
+        let depositor: &mut Depositor = &mut ctx.accounts.depositor;
+        let request = &mut ctx.accounts.withdrawal_request;
+        require_keys_eq!(depositor.owner, ctx.accounts.user.key(), CustomError::Unauthorized);
+
+        if request.sol_amount > 0 || request.token_amount > 0 {
+            require!(request.epoch == config.withdrawal_epoch, CustomError::WithdrawalPending);
+        }
+        request.owner = ctx.accounts.user.key();
+        request.epoch = config.withdrawal_epoch;
+
+        if sol_amount > 0 {
+            let (queued, lots) = debit_sol(config, depositor, sol_amount)?;
+            restore_lots(&mut request.sol_lots, &lots);
+            config.queued_sol = config.queued_sol.checked_add(queued).ok_or(CustomError::MathOverflow)?;
+            request.sol_amount = request.sol_amount.checked_add(queued).ok_or(CustomError::MathOverflow)?;
+        }
+
+        if token_amount > 0 {
+            let mint = ctx.accounts.token_mint.as_ref().ok_or(CustomError::TokenAccountsRequired)?.key();
+            require_keys_eq!(mint, depositor.token_mint, CustomError::InvalidMint);
+            if request.token_amount > 0 {
+                require_keys_eq!(mint, request.token_mint, CustomError::InvalidMint);
+            }
+
+            let (queued, lots) = debit_token(config, depositor, &mint, token_amount)?;
+            restore_lots(&mut request.token_lots, &lots);
+            let entry = config.accepted_mints.iter_mut().find(|entry| entry.mint == mint).ok_or(CustomError::InvalidMint)?;
+            entry.queued = entry.queued.checked_add(queued).ok_or(CustomError::MathOverflow)?;
+            request.token_mint = mint;
+            request.token_amount = request.token_amount.checked_add(queued).ok_or(CustomError::MathOverflow)?;
+        }
+
+        emit!(WithdrawalRequested {
+            user: request.owner,
+            epoch: request.epoch,
+            sol_amount,
+            token_mint: request.token_mint,
+            token_amount,
+            queued_sol: request.sol_amount,
+            queued_token: request.token_amount,
+            sol_balance: depositor.sol_amount,
+            token_balance: depositor.token_amount,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // Puts a request from the open epoch back into the depositor's balance under its original deposit
+    // times; lock penalties are not refunded
+    pub fn cancel_withdrawal(ctx: Context<CancelWithdrawal>) -> Result<()> {
+        require!(!ctx.accounts.config.withdrawals_paused, CustomError::Paused);
+        let config: &mut VaultConfig = &mut ctx.accounts.config;
+        let depositor: &mut Depositor = &mut ctx.accounts.depositor;
+        let request = &ctx.accounts.withdrawal_request;
+        require!(request.epoch == config.withdrawal_epoch, CustomError::WithdrawalSettled);
+        let now = Clock::get()?.unix_timestamp;
+
+        if request.sol_amount > 0 {
+            depositor.settle_sol_rewards(config.sol_reward_index)?;
+            depositor.sol_amount = depositor.sol_amount.checked_add(request.sol_amount).ok_or(CustomError::MathOverflow)?;
+            depositor.sol_reward_debt = accrued_rewards(depositor.sol_amount, config.sol_reward_index)?;
+            restore_lots(&mut depositor.sol_lots, &request.sol_lots);
+            config.total_sol = config.total_sol.checked_add(request.sol_amount).ok_or(CustomError::MathOverflow)?;
+            config.queued_sol = config.queued_sol.saturating_sub(request.sol_amount);
+        }
+
+        if request.token_amount > 0 {
+            // The depositor may have moved on to another token in the meantime
+            if depositor.token_amount == 0 {
+                depositor.token_mint = request.token_mint;
+                depositor.token_lots.clear();
+            }
+            require_keys_eq!(depositor.token_mint, request.token_mint, CustomError::InvalidMint);
+
+            let entry = config.accepted_mints.iter_mut().find(|entry| entry.mint == request.token_mint).ok_or(CustomError::InvalidMint)?;
+            depositor.settle_token_rewards(entry.reward_index)?;
+            depositor.token_amount = depositor.token_amount.checked_add(request.token_amount).ok_or(CustomError::MathOverflow)?;
+            depositor.token_reward_debt = accrued_rewards(depositor.token_amount, entry.reward_index)?;
+            restore_lots(&mut depositor.token_lots, &request.token_lots);
+            entry.total_deposited = entry.total_deposited.checked_add(request.token_amount).ok_or(CustomError::MathOverflow)?;
+            entry.queued = entry.queued.saturating_sub(request.token_amount);
+        }
+
+        emit!(WithdrawalCancelled {
+            user: request.owner,
+            epoch: request.epoch,
+            sol_amount: request.sol_amount,
+            token_mint: request.token_mint,
+            token_amount: request.token_amount,
+            sol_balance: depositor.sol_amount,
+            token_balance: depositor.token_amount,
+            timestamp: now,
+        });
+        Ok(())
+    }
+
+    // Closes the open epoch once the admin has the liquidity in place; its requests become claimable
+    pub fn settle_withdrawals(ctx: Context<SettleWithdrawals>) -> Result<()> {
+        require!(!ctx.accounts.config.withdrawals_paused, CustomError::Paused);
+        let config = &mut ctx.accounts.config;
+        require_keys_eq!(config.admin, ctx.accounts.admin.key(), CustomError::Unauthorized);
+
+        config.withdrawal_epoch = config.withdrawal_epoch.checked_add(1).ok_or(CustomError::MathOverflow)?;
+
+        emit!(WithdrawalsSettled {
+            admin: config.admin,
+            epoch: config.withdrawal_epoch - 1,
+            queued_sol: config.queued_sol,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn claim_withdrawal(ctx: Context<ClaimWithdrawal>) -> Result<()> {
+        require!(!ctx.accounts.config.withdrawals_paused, CustomError::Paused);
+        let request = &ctx.accounts.withdrawal_request;
+        require!(request.epoch < ctx.accounts.config.withdrawal_epoch, CustomError::WithdrawalNotSettled);
+
+        if request.sol_amount > 0 {
+            let bump = ctx.bumps.vault_pda;
+            let seeds = &[b"vault_pda".as_ref(), &[bump]];
+            let signer = &[&seeds[..]];
+
+            let ix = anchor_lang::solana_program::system_instruction::transfer(
+                &ctx.accounts.vault_pda.key(),
+                &ctx.accounts.user.key(),
+                request.sol_amount,
+            );
+            anchor_lang::solana_program::program::invoke_signed(
+                &ix,
+                &[
+                    ctx.accounts.vault_pda.to_account_info(),
+                    ctx.accounts.user.to_account_info(),
+                ],
+                signer,
+            )?;
+
+            let config = &mut ctx.accounts.config;
+            config.queued_sol = config.queued_sol.saturating_sub(request.sol_amount);
+        }
+
+        if request.token_amount > 0 {
+            let (Some(vault_token_account), Some(user_token_account), Some(token_mint), Some(token_program)) = (
+                &ctx.accounts.vault_token_account,
+                &ctx.accounts.user_token_account,
+                &ctx.accounts.token_mint,
+                &ctx.accounts.token_program,
+            ) else {
+                return err!(CustomError::TokenAccountsRequired);
+            };
+            let mint = token_mint.key();
+            require_keys_eq!(mint, request.token_mint, CustomError::InvalidMint);
+
+            let config_key = ctx.accounts.config.key();
+            let seeds = &[b"vault_authority".as_ref(), config_key.as_ref(), &[ctx.accounts.config.authority_bump]];
+            let signer = &[&seeds[..]];
 
-            depositor.sol_amount = depositor.sol_amount.checked_sub(sol_amount).ok_or(CustomError::MathOverflow)?;
+            let cpi_accounts = TransferChecked {
+                from: vault_token_account.to_account_info(),
+                mint: token_mint.to_account_info(),
+                to: user_token_account.to_account_info(),
+                authority: ctx.accounts.vault_authority.to_account_info(),
+            };
+
+            let cpi_ctx = CpiContext::new_with_signer(token_program.to_account_info(), cpi_accounts, signer);
+            token_interface::transfer_checked(cpi_ctx, request.token_amount, token_mint.decimals)?;
+
+            let config = &mut ctx.accounts.config;
+            if let Some(entry) = config.accepted_mints.iter_mut().find(|entry| entry.mint == mint) {
+                entry.queued = entry.queued.saturating_sub(request.token_amount);
+            }
         }
 
-        // Withdraw USDC
-        if usdc_amount > 0 {
-            require!(depositor.usdc_amount >= usdc_amount, CustomError::InsufficientBalance);
+        emit!(WithdrawalClaimed {
+            user: request.owner,
+            epoch: request.epoch,
+            sol_amount: request.sol_amount,
+            token_mint: request.token_mint,
+            token_amount: request.token_amount,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // Treasury penalties and anything else not owed to depositors can be swept by the admin
+    pub fn withdraw_treasury(ctx: Context<WithdrawTreasury>, sol_amount: u64, token_amount: u64) -> Result<()> {
+        let config = &ctx.accounts.config;
+        require_keys_eq!(config.admin, ctx.accounts.admin.key(), CustomError::Unauthorized);
 
-            // Validate mint
-            require_keys_eq!(ctx.accounts.usdc_mint.key(), Pubkey::from_str(USDC_MINT).unwrap(), CustomError::InvalidMint);
-            require_keys_eq!(ctx.accounts.user_usdc_account.mint, ctx.accounts.usdc_mint.key(), CustomError::InvalidMint);
-            require_keys_eq!(ctx.accounts.vault_usdc_account.mint, ctx.accounts.usdc_mint.key(), CustomError::InvalidMint);
+        if sol_amount > 0 {
+            let surplus = ctx.accounts.vault_pda.lamports()
+                .saturating_sub(config.total_sol)
+                .saturating_sub(config.queued_sol)
+                .saturating_sub(Rent::get()?.minimum_balance(0));
+            require!(sol_amount <= surplus, CustomError::InsufficientBalance);
 
-            let bump = ctx.bumps.vault_usdc_account;
+            let bump = ctx.bumps.vault_pda;
             let seeds = &[b"vault_pda".as_ref(), &[bump]];
             let signer = &[&seeds[..]];
 
-            let cpi_accounts = Transfer {
-                from: ctx.accounts.vault_usdc_account.to_account_info(),
-                to: ctx.accounts.user_usdc_account.to_account_info(),
-                authority: ctx.accounts.vault_usdc_account.to_account_info(),
+            let ix = anchor_lang::solana_program::system_instruction::transfer(
+                &ctx.accounts.vault_pda.key(),
+                &ctx.accounts.admin.key(),
+                sol_amount,
+            );
+            anchor_lang::solana_program::program::invoke_signed(
+                &ix,
+                &[
+                    ctx.accounts.vault_pda.to_account_info(),
+                    ctx.accounts.admin.to_account_info(),
+                ],
+                signer,
+            )?;
+        }
+
+        if token_amount > 0 {
+            let mint = ctx.accounts.token_mint.key();
+            let owed = config.accepted_mints.iter().find(|entry| entry.mint == mint).map_or(0, |entry| entry.total_deposited.saturating_add(entry.queued));
+            let surplus = ctx.accounts.vault_token_account.amount.saturating_sub(owed);
+            require!(token_amount <= surplus, CustomError::InsufficientBalance);
+
+            let config_key = config.key();
+            let seeds = &[b"vault_authority".as_ref(), config_key.as_ref(), &[config.authority_bump]];
+            let signer = &[&seeds[..]];
+
+            let cpi_accounts = TransferChecked {
+                from: ctx.accounts.vault_token_account.to_account_info(),
+                mint: ctx.accounts.token_mint.to_account_info(),
+                to: ctx.accounts.admin_token_account.to_account_info(),
+                authority: ctx.accounts.vault_authority.to_account_info(),
             };
 
             let cpi_ctx = CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), cpi_accounts, signer);
-            token::transfer(cpi_ctx, usdc_amount)?;
+            token_interface::transfer_checked(cpi_ctx, token_amount, ctx.accounts.token_mint.decimals)?;
+        }
 
-            depositor.usdc_amount = depositor.usdc_amount.checked_sub(usdc_amount).ok_or(CustomError::MathOverflow)?;
+        emit!(TreasuryWithdrawn {
+            admin: config.admin,
+            sol_amount,
+            token_mint: ctx.accounts.token_mint.key(),
+            token_amount,
+            vault_sol_balance: ctx.accounts.vault_pda.lamports(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // Grows a deployed position to the current layout. Its balances are already booked by
+    // `initialize_config` and `migrate_legacy_token_account`, so token positions wait for the latter;
+    // it earns shared penalties from now on, and having been deposited before lockups existed it carries no lock.
+    pub fn migrate_depositor(ctx: Context<MigrateDepositor>) -> Result<()> {
+        let info = ctx.accounts.depositor.to_account_info();
+        require_keys_eq!(*info.owner, crate::ID, CustomError::NotLegacyAccount);
+        require!(info.data_len() == LEGACY_DEPOSITOR_SPACE, CustomError::NotLegacyAccount);
+        require!(info.try_borrow_data()?[..8] == Depositor::DISCRIMINATOR, CustomError::NotLegacyAccount);
+
+        // Zeroed bytes decode as the default of every appended field; the user tops up the rent
+        let top_up = Rent::get()?.minimum_balance(DEPOSITOR_SPACE).saturating_sub(info.lamports());
+        if top_up > 0 {
+            let ix = anchor_lang::solana_program::system_instruction::transfer(&ctx.accounts.user.key(), info.key, top_up);
+            anchor_lang::solana_program::program::invoke(
+                &ix,
+                &[
+                    ctx.accounts.user.to_account_info(),
+                    info.clone(),
+                    ctx.accounts.system_program.to_account_info(),
+                ],
+            )?;
         }
+        info.realloc(DEPOSITOR_SPACE, true)?;
 
+        let config = &ctx.accounts.config;
+        let mut depositor = Depositor::try_deserialize(&mut &info.data.borrow()[..])?;
+        require_keys_eq!(depositor.owner, ctx.accounts.user.key(), CustomError::Unauthorized);
+        if depositor.token_amount > 0 {
+            require!(ctx.accounts.legacy_token_account.data_is_empty(), CustomError::LegacyTokensNotMigrated);
+        }
+
+        depositor.sol_reward_debt = accrued_rewards(depositor.sol_amount, config.sol_reward_index)?;
+        let token_reward_index = config.accepted_mints.iter()
+            .find(|entry| entry.mint == depositor.token_mint)
+            .map_or(0, |entry| entry.reward_index);
+        depositor.token_reward_debt = accrued_rewards(depositor.token_amount, token_reward_index)?;
+        depositor.try_serialize(&mut &mut info.data.borrow_mut()[..])?;
+
+        emit!(DepositorMigrated {
+            user: depositor.owner,
+            sol_amount: depositor.sol_amount,
+            token_mint: depositor.token_mint,
+            token_amount: depositor.token_amount,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
         Ok(())
     }
+
+    // Per-mint token accounts opened before `vault_authority` existed are their own authority; hand them over
+    pub fn migrate_token_authority(ctx: Context<MigrateTokenAuthority>) -> Result<()> {
+        let config = &ctx.accounts.config;
+        require_keys_eq!(config.admin, ctx.accounts.admin.key(), CustomError::Unauthorized);
+
+        let mint = ctx.accounts.token_mint.key();
+        let bump = ctx.bumps.vault_token_account;
+        let seeds = &[b"vault_token_account".as_ref(), mint.as_ref(), &[bump]];
+        let signer = &[&seeds[..]];
+
+        let cpi_accounts = SetAuthority {
+            current_authority: ctx.accounts.vault_token_account.to_account_info(),
+            account_or_mint: ctx.accounts.vault_token_account.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), cpi_accounts, signer);
+        token_interface::set_authority(cpi_ctx, AuthorityType::AccountOwner, Some(ctx.accounts.vault_authority.key()))?;
+
+        emit!(TokenAuthorityMigrated {
+            admin: config.admin,
+            mint,
+            token_account: ctx.accounts.vault_token_account.key(),
+            authority: ctx.accounts.vault_authority.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // The original `[b"vault_usdc_account"]` account is its own authority and sits outside the
+    // per-mint layout, so its balance is moved into the mint's vault token account and it is closed.
+    // The moved tokens are booked as owed to the depositors who put them there.
+    pub fn migrate_legacy_token_account(ctx: Context<MigrateLegacyTokenAccount>) -> Result<()> {
+        require_keys_eq!(ctx.accounts.config.admin, ctx.accounts.admin.key(), CustomError::Unauthorized);
+
+        let bump = ctx.bumps.legacy_token_account;
+        let seeds = &[b"vault_usdc_account".as_ref(), &[bump]];
+        let signer = &[&seeds[..]];
+
+        let balance_before = ctx.accounts.vault_token_account.amount;
+        let amount = ctx.accounts.legacy_token_account.amount;
+        if amount > 0 {
+            let cpi_accounts = TransferChecked {
+                from: ctx.accounts.legacy_token_account.to_account_info(),
+                mint: ctx.accounts.token_mint.to_account_info(),
+                to: ctx.accounts.vault_token_account.to_account_info(),
+                authority: ctx.accounts.legacy_token_account.to_account_info(),
+            };
+            let cpi_ctx = CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), cpi_accounts, signer);
+            token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.token_mint.decimals)?;
+        }
+
+        let cpi_accounts = CloseAccount {
+            account: ctx.accounts.legacy_token_account.to_account_info(),
+            destination: ctx.accounts.admin.to_account_info(),
+            authority: ctx.accounts.legacy_token_account.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), cpi_accounts, signer);
+        token_interface::close_account(cpi_ctx)?;
+
+        ctx.accounts.vault_token_account.reload()?;
+        let received = ctx.accounts.vault_token_account.amount
+            .checked_sub(balance_before)
+            .ok_or(CustomError::MathOverflow)?;
+
+        let mint = ctx.accounts.token_mint.key();
+        let config = &mut ctx.accounts.config;
+        let entry = config.accepted_mints.iter_mut().find(|entry| entry.mint == mint).ok_or(CustomError::InvalidMint)?;
+        entry.total_deposited = entry.total_deposited.checked_add(received).ok_or(CustomError::MathOverflow)?;
+
+        emit!(LegacyTokenAccountMigrated {
+            admin: config.admin,
+            mint,
+            legacy_account: ctx.accounts.legacy_token_account.key(),
+            token_account: ctx.accounts.vault_token_account.key(),
+            amount: received,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+}
+
+// The config singleton may only be created by the deployer: the upgrade authority recorded in this
+// program's ProgramData, or with the `fixed-owner` feature the key baked in through INITIAL_OWNER at build time
+fn require_deployer(signer: &Pubkey, program_data: &Option<Account<ProgramData>>) -> Result<()> {
+    #[cfg(feature = "fixed-owner")]
+    {
+        let _ = program_data;
+        let initial_owner = Pubkey::from_str(env!("INITIAL_OWNER")).map_err(|_| error!(CustomError::Unauthorized))?;
+        require_keys_eq!(*signer, initial_owner, CustomError::Unauthorized);
+    }
+
+    #[cfg(not(feature = "fixed-owner"))]
+    {
+        let program_data = program_data.as_ref().ok_or(error!(CustomError::Unauthorized))?;
+        require!(program_data.upgrade_authority_address == Some(*signer), CustomError::Unauthorized);
+    }
+    Ok(())
+}
+
+// A cap of zero means no cap
+fn remaining_under_cap(cap: u64, used: u64) -> u64 {
+    if cap == 0 {
+        u64::MAX
+    } else {
+        cap.saturating_sub(used)
+    }
+}
+
+// Share of penalties a balance has earned at the given reward index
+fn accrued_rewards(amount: u64, reward_index: u128) -> Result<u128> {
+    Ok((amount as u128).checked_mul(reward_index).ok_or(CustomError::MathOverflow)? / REWARD_SCALE)
+}
+
+// Takes `amount` out of a depositor's SOL balance, lots first, and returns what is left to pay after any
+// lock penalty along with the lot pieces behind it
+fn debit_sol(config: &mut VaultConfig, depositor: &mut Depositor, amount: u64) -> Result<(u64, Vec<Lot>)> {
+    depositor.settle_sol_rewards(config.sol_reward_index)?;
+    require!(depositor.sol_amount >= amount, CustomError::InsufficientBalance);
+    let mut lots = take_lots(&mut depositor.sol_lots, amount);
+    let penalty = early_exit_penalty(&config.lockup, locked_amount(&lots, config.lockup.lock_period, Clock::get()?.unix_timestamp))?;
+    trim_newest(&mut lots, penalty);
+
+    depositor.sol_amount = depositor.sol_amount.checked_sub(amount).ok_or(CustomError::MathOverflow)?;
+    depositor.sol_reward_debt = accrued_rewards(depositor.sol_amount, config.sol_reward_index)?;
+    config.total_sol = config.total_sol.saturating_sub(amount);
+    share_penalty(&config.lockup, &mut config.sol_reward_index, &mut config.total_sol, penalty)?;
+    Ok((amount - penalty, lots))
+}
+
+fn debit_token(config: &mut VaultConfig, depositor: &mut Depositor, mint: &Pubkey, amount: u64) -> Result<(u64, Vec<Lot>)> {
+    let lockup = config.lockup;
+    let entry = config.accepted_mints.iter_mut().find(|entry| entry.mint == *mint).ok_or(CustomError::InvalidMint)?;
+    depositor.settle_token_rewards(entry.reward_index)?;
+    require!(depositor.token_amount >= amount, CustomError::InsufficientBalance);
+    let mut lots = take_lots(&mut depositor.token_lots, amount);
+    let penalty = early_exit_penalty(&lockup, locked_amount(&lots, lockup.lock_period, Clock::get()?.unix_timestamp))?;
+    trim_newest(&mut lots, penalty);
+
+    depositor.token_amount = depositor.token_amount.checked_sub(amount).ok_or(CustomError::MathOverflow)?;
+    depositor.token_reward_debt = accrued_rewards(depositor.token_amount, entry.reward_index)?;
+    entry.total_deposited = entry.total_deposited.saturating_sub(amount);
+    share_penalty(&lockup, &mut entry.reward_index, &mut entry.total_deposited, penalty)?;
+    Ok((amount - penalty, lots))
+}
+
+// Withdrawing a still-locked amount is refused, or costs the configured penalty on that part
+fn early_exit_penalty(lockup: &Lockup, locked: u64) -> Result<u64> {
+    if locked == 0 {
+        return Ok(0);
+    }
+
+    require!(lockup.early_exit_penalty_bps > 0, CustomError::Locked);
+    Ok(((locked as u128) * (lockup.early_exit_penalty_bps as u128) / (BPS_DENOMINATOR as u128)) as u64)
+}
+
+// Once MAX_LOTS is reached the two oldest lots merge under the later timestamp, so nothing unlocks early
+fn add_lot(lots: &mut Vec<Lot>, amount: u64, now: i64) {
+    lots.push(Lot { amount, deposit_time: now });
+    if lots.len() > MAX_LOTS {
+        let oldest = lots.remove(0);
+        lots[0].amount = lots[0].amount.saturating_add(oldest.amount);
+        lots[0].deposit_time = lots[0].deposit_time.max(oldest.deposit_time);
+    }
+}
+
+// Puts lot pieces back in deposit order, merging the oldest as in `add_lot` once MAX_LOTS is exceeded
+fn restore_lots(lots: &mut Vec<Lot>, pieces: &[Lot]) {
+    for piece in pieces {
+        let at = lots.partition_point(|lot| lot.deposit_time <= piece.deposit_time);
+        lots.insert(at, *piece);
+    }
+    while lots.len() > MAX_LOTS {
+        let oldest = lots.remove(0);
+        lots[0].amount = lots[0].amount.saturating_add(oldest.amount);
+        lots[0].deposit_time = lots[0].deposit_time.max(oldest.deposit_time);
+    }
+}
+
+// Withdrawals drain lots first-in-first-out; returns the pieces taken
+fn take_lots(lots: &mut Vec<Lot>, amount: u64) -> Vec<Lot> {
+    let mut remaining = amount;
+    let mut taken = Vec::new();
+    while remaining > 0 && !lots.is_empty() {
+        let piece = remaining.min(lots[0].amount);
+        taken.push(Lot { amount: piece, deposit_time: lots[0].deposit_time });
+        lots[0].amount -= piece;
+        remaining -= piece;
+        if lots[0].amount == 0 {
+            lots.remove(0);
+        }
+    }
+    taken
+}
+
+// The part of the pieces that is still locked
+fn locked_amount(pieces: &[Lot], lock_period: i64, now: i64) -> u64 {
+    pieces.iter()
+        .filter(|lot| now < lot.deposit_time.saturating_add(lock_period))
+        .map(|lot| lot.amount)
+        .sum()
+}
+
+// Penalties come out of the most recently deposited pieces
+fn trim_newest(pieces: &mut Vec<Lot>, amount: u64) {
+    let mut remaining = amount;
+    while remaining > 0 && !pieces.is_empty() {
+        let newest = pieces.len() - 1;
+        let cut = remaining.min(pieces[newest].amount);
+        pieces[newest].amount -= cut;
+        remaining -= cut;
+        if pieces[newest].amount == 0 {
+            pieces.pop();
+        }
+    }
+}
+
+fn weighted_age(lots: &[Lot], now: i64) -> Result<i64> {
+    let mut total: u128 = 0;
+    let mut weighted: u128 = 0;
+    for lot in lots {
+        let age = now.saturating_sub(lot.deposit_time).max(0) as u128;
+        total += lot.amount as u128;
+        weighted = weighted
+            .checked_add((lot.amount as u128).checked_mul(age).ok_or(CustomError::MathOverflow)?)
+            .ok_or(CustomError::MathOverflow)?;
+    }
+
+    if total == 0 {
+        return Ok(0);
+    }
+    Ok((weighted / total) as i64)
+}
+
+// Penalties shared with depositors raise every balance in the pool; otherwise (or with nobody left)
+// they stay in custody as treasury surplus for the admin
+fn share_penalty(lockup: &Lockup, reward_index: &mut u128, total: &mut u64, penalty: u64) -> Result<()> {
+    if penalty == 0 || lockup.penalty_destination != PenaltyDestination::Depositors || *total == 0 {
+        return Ok(());
+    }
+
+    let per_deposit = (penalty as u128) * REWARD_SCALE / (*total as u128);
+    *reward_index = reward_index.checked_add(per_deposit).ok_or(CustomError::MathOverflow)?;
+    *total = total.checked_add(penalty).ok_or(CustomError::MathOverflow)?;
+    Ok(())
+}
+
+// Only Token-2022 extensions that keep the vault's balances predictable are accepted;
+// transfer fees are netted out of deposits, everything else beyond metadata is refused
+fn require_supported_mint(mint: &AccountInfo) -> Result<()> {
+    if *mint.owner != token_interface::spl_token_2022::ID {
+        return Ok(());
+    }
+
+    let data = mint.try_borrow_data()?;
+    let state = StateWithExtensions::<token_interface::spl_token_2022::state::Mint>::unpack(&data)?;
+    for extension in state.get_extension_types()? {
+        match extension {
+            ExtensionType::TransferFeeConfig
+            | ExtensionType::InterestBearingConfig
+            | ExtensionType::MetadataPointer
+            | ExtensionType::TokenMetadata => {}
+            _ => return err!(CustomError::UnsupportedMintExtension),
+        }
+    }
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct InitializeConfig<'info> {
+    #[account(init, payer = admin, space = 8 + 32 + 1 + 1 + (8 * 3) + 8 + 4 + (32 + 1 + 1 + 8 + 8 + 16 + 8) * MAX_ACCEPTED_MINTS + (8 + 2 + 1) + 16 + 8 + 8 + 1, seeds = [b"vault_config"], bump)]
+    pub config: Account<'info, VaultConfig>,
+
+    /// CHECK: PDA signing for every vault token account, holds no data
+    #[account(seeds = [b"vault_authority", config.key().as_ref()], bump)]
+    pub vault_authority: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(seeds = [b"vault_pda"], bump)]
+    /// CHECK: PDA holding SOL; only read
+    pub vault_pda: AccountInfo<'info>,
+
+    /// This program's ProgramData; may be omitted when built with `fixed-owner`
+    #[account(seeds = [crate::ID.as_ref()], bump, seeds::program = bpf_loader_upgradeable::ID)]
+    pub program_data: Option<Account<'info, ProgramData>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetPause<'info> {
+    #[account(mut, seeds = [b"vault_config"], bump)]
+    pub config: Account<'info, VaultConfig>,
+
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetLockup<'info> {
+    #[account(mut, seeds = [b"vault_config"], bump)]
+    pub config: Account<'info, VaultConfig>,
+
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ConfigureMint<'info> {
+    #[account(mut, seeds = [b"vault_config"], bump)]
+    pub config: Account<'info, VaultConfig>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetDepositCaps<'info> {
+    #[account(mut, seeds = [b"vault_config"], bump)]
+    pub config: Account<'info, VaultConfig>,
+
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(user: Pubkey)]
+pub struct RemainingCapacity<'info> {
+    #[account(seeds = [b"vault_config"], bump)]
+    pub config: Account<'info, VaultConfig>,
+
+    #[account(seeds = [b"depositor", user.as_ref()], bump)]
+    pub depositor: Option<Account<'info, Depositor>>,
+}
+
+#[derive(Accounts)]
+#[instruction(user: Pubkey)]
+pub struct PositionAge<'info> {
+    #[account(seeds = [b"depositor", user.as_ref()], bump)]
+    pub depositor: Account<'info, Depositor>,
 }
 
 #[derive(Accounts)]
@@ -135,10 +996,13 @@ pub struct DepositSol<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
 
+    #[account(mut, seeds = [b"vault_config"], bump)]
+    pub config: Account<'info, VaultConfig>,
+
     #[account(
         init_if_needed,
         payer = user,
-        space = 8 + 32 + 1 + 8 + 8 + 32 + 8,
+        space = DEPOSITOR_SPACE,
         seeds = [b"depositor", user.key().as_ref()],
         bump
     )]
@@ -152,34 +1016,43 @@ pub struct DepositSol<'info> {
 }
 
 #[derive(Accounts)]
-pub struct DepositUsdc<'info> {
+pub struct DepositToken<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
 
+    #[account(mut, seeds = [b"vault_config"], bump)]
+    pub config: Account<'info, VaultConfig>,
+
     #[account(
         init_if_needed,
         payer = user,
-        space = 8 + 32 + 1 + 8 + 8 + 32 + 8,
+        space = DEPOSITOR_SPACE,
         seeds = [b"depositor", user.key().as_ref()],
         bump
     )]
     pub depositor: Account<'info, Depositor>,
 
+    /// CHECK: PDA signing for every vault token account, holds no data
+    #[account(seeds = [b"vault_authority", config.key().as_ref()], bump = config.authority_bump)]
+    pub vault_authority: UncheckedAccount<'info>,
+
     #[account(
         init_if_needed,
         payer = user,
-        token::mint = usdc_mint,
-        token::authority = vault_usdc_account,
-        seeds = [b"vault_usdc_account"],
+        token::mint = token_mint,
+        token::authority = vault_authority,
+        token::token_program = token_program,
+        seeds = [b"vault_token_account", token_mint.key().as_ref()],
         bump
     )]
-    pub vault_usdc_account: Account<'info, TokenAccount>,
+    pub vault_token_account: InterfaceAccount<'info, TokenAccount>,
 
-    #[account(mut, token::mint = usdc_mint)]
-    pub user_usdc_account: Account<'info, TokenAccount>,
+    #[account(mut, token::mint = token_mint, token::token_program = token_program)]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
 
-    pub usdc_mint: Account<'info, Mint>,
-    pub token_program: Program<'info, Token>,
+    #[account(mint::token_program = token_program)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+    pub token_program: Interface<'info, TokenInterface>,
     pub system_program: Program<'info, System>,
 }
 
@@ -188,6 +1061,9 @@ pub struct Withdraw<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
 
+    #[account(mut, seeds = [b"vault_config"], bump)]
+    pub config: Account<'info, VaultConfig>,
+
     #[account(mut, seeds = [b"depositor", user.key().as_ref()], bump)]
     pub depositor: Account<'info, Depositor>,
 
@@ -195,14 +1071,292 @@ pub struct Withdraw<'info> {
     /// CHECK: Vault PDA holding SOL
     pub vault_pda: AccountInfo<'info>,
 
-    #[account(mut, token::mint = usdc_mint, seeds = [b"vault_usdc_account"], bump)]
-    pub vault_usdc_account: Account<'info, TokenAccount>,
+    /// CHECK: PDA signing for every vault token account, holds no data
+    #[account(seeds = [b"vault_authority", config.key().as_ref()], bump = config.authority_bump)]
+    pub vault_authority: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        token::mint = token_mint,
+        token::token_program = token_program,
+        seeds = [b"vault_token_account", token_mint.key().as_ref()],
+        bump
+    )]
+    pub vault_token_account: InterfaceAccount<'info, TokenAccount>,
 
-    #[account(mut, token::mint = usdc_mint)]
-    pub user_usdc_account: Account<'info, TokenAccount>,
+    #[account(mut, token::mint = token_mint, token::token_program = token_program)]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
 
-    pub usdc_mint: Account<'info, Mint>,
-    pub token_program: Program<'info, Token>,
+    #[account(mint::token_program = token_program)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct RequestWithdraw<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(mut, seeds = [b"vault_config"], bump)]
+    pub config: Account<'info, VaultConfig>,
+
+    #[account(mut, seeds = [b"depositor", user.key().as_ref()], bump)]
+    pub depositor: Account<'info, Depositor>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + 32 + 8 + 8 + 32 + 8 + (4 + (8 + 8) * MAX_LOTS) * 2,
+        seeds = [b"withdrawal_request", user.key().as_ref()],
+        bump
+    )]
+    pub withdrawal_request: Account<'info, WithdrawalRequest>,
+
+    // Only needed when tokens are requested
+    pub token_mint: Option<InterfaceAccount<'info, Mint>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CancelWithdrawal<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(mut, seeds = [b"vault_config"], bump)]
+    pub config: Account<'info, VaultConfig>,
+
+    #[account(mut, seeds = [b"depositor", user.key().as_ref()], bump)]
+    pub depositor: Account<'info, Depositor>,
+
+    #[account(mut, close = user, seeds = [b"withdrawal_request", user.key().as_ref()], bump)]
+    pub withdrawal_request: Account<'info, WithdrawalRequest>,
+}
+
+#[derive(Accounts)]
+pub struct SettleWithdrawals<'info> {
+    #[account(mut, seeds = [b"vault_config"], bump)]
+    pub config: Account<'info, VaultConfig>,
+
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimWithdrawal<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(mut, seeds = [b"vault_config"], bump)]
+    pub config: Account<'info, VaultConfig>,
+
+    #[account(mut, close = user, seeds = [b"withdrawal_request", user.key().as_ref()], bump)]
+    pub withdrawal_request: Account<'info, WithdrawalRequest>,
+
+    #[account(mut, seeds = [b"vault_pda"], bump)]
+    /// CHECK: Vault PDA holding SOL
+    pub vault_pda: AccountInfo<'info>,
+
+    /// CHECK: PDA signing for every vault token account, holds no data
+    #[account(seeds = [b"vault_authority", config.key().as_ref()], bump = config.authority_bump)]
+    pub vault_authority: UncheckedAccount<'info>,
+
+    // The token accounts are only needed when the request holds tokens; `token::mint` ties the
+    // custody seed below to `token_mint`
+    #[account(
+        mut,
+        token::mint = token_mint,
+        token::token_program = token_program,
+        seeds = [b"vault_token_account", vault_token_account.mint.as_ref()],
+        bump
+    )]
+    pub vault_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut, token::mint = token_mint, token::token_program = token_program)]
+    pub user_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mint::token_program = token_program)]
+    pub token_mint: Option<InterfaceAccount<'info, Mint>>,
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawTreasury<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(seeds = [b"vault_config"], bump)]
+    pub config: Account<'info, VaultConfig>,
+
+    #[account(mut, seeds = [b"vault_pda"], bump)]
+    /// CHECK: Vault PDA holding SOL
+    pub vault_pda: AccountInfo<'info>,
+
+    /// CHECK: PDA signing for every vault token account, holds no data
+    #[account(seeds = [b"vault_authority", config.key().as_ref()], bump = config.authority_bump)]
+    pub vault_authority: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        token::mint = token_mint,
+        token::token_program = token_program,
+        seeds = [b"vault_token_account", token_mint.key().as_ref()],
+        bump
+    )]
+    pub vault_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut, token::mint = token_mint, token::token_program = token_program)]
+    pub admin_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mint::token_program = token_program)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateTokenAuthority<'info> {
+    #[account(seeds = [b"vault_config"], bump)]
+    pub config: Account<'info, VaultConfig>,
+
+    pub admin: Signer<'info>,
+
+    /// CHECK: PDA signing for every vault token account, holds no data
+    #[account(seeds = [b"vault_authority", config.key().as_ref()], bump = config.authority_bump)]
+    pub vault_authority: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        token::mint = token_mint,
+        token::token_program = token_program,
+        seeds = [b"vault_token_account", token_mint.key().as_ref()],
+        bump
+    )]
+    pub vault_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mint::token_program = token_program)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateDepositor<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(seeds = [b"vault_config"], bump)]
+    pub config: Account<'info, VaultConfig>,
+
+    /// CHECK: still in its deployed layout; checked in `migrate_depositor`
+    #[account(mut, seeds = [b"depositor", user.key().as_ref()], bump)]
+    pub depositor: UncheckedAccount<'info>,
+
+    /// CHECK: must already be closed by `migrate_legacy_token_account` for token positions
+    #[account(seeds = [b"vault_usdc_account"], bump)]
+    pub legacy_token_account: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateLegacyTokenAccount<'info> {
+    #[account(mut, seeds = [b"vault_config"], bump)]
+    pub config: Account<'info, VaultConfig>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    /// CHECK: PDA signing for every vault token account, holds no data
+    #[account(seeds = [b"vault_authority", config.key().as_ref()], bump = config.authority_bump)]
+    pub vault_authority: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        token::mint = token_mint,
+        token::authority = legacy_token_account,
+        token::token_program = token_program,
+        seeds = [b"vault_usdc_account"],
+        bump
+    )]
+    pub legacy_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = admin,
+        token::mint = token_mint,
+        token::authority = vault_authority,
+        token::token_program = token_program,
+        seeds = [b"vault_token_account", token_mint.key().as_ref()],
+        bump
+    )]
+    pub vault_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mint::token_program = token_program)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[account]
+pub struct VaultConfig {
+    pub admin: Pubkey,               // 32 bytes
+    pub deposits_paused: bool,       // 1 byte
+    pub withdrawals_paused: bool,    // 1 byte
+    pub caps: DepositCaps,           // 24 bytes
+    pub total_sol: u64,              // 8 bytes
+    pub accepted_mints: Vec<AcceptedMint>,
+    pub lockup: Lockup,              // 11 bytes
+    pub sol_reward_index: u128,      // 16 bytes
+    pub withdrawal_epoch: u64,       // 8 bytes, epoch currently taking requests
+    pub queued_sol: u64,             // 8 bytes, requested but not yet claimed
+    pub authority_bump: u8,          // 1 byte, bump of the `vault_authority` signer PDA
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct AcceptedMint {
+    pub mint: Pubkey,
+    pub decimals: u8,
+    pub enabled: bool,
+    pub deposit_cap: u64,        // 0 = uncapped
+    pub total_deposited: u64,
+    pub reward_index: u128,      // penalties shared per deposited unit, scaled by REWARD_SCALE
+    pub queued: u64,             // requested for withdrawal but not yet claimed
+}
+
+#[account]
+pub struct WithdrawalRequest {
+    pub owner: Pubkey,           // 32 bytes
+    pub epoch: u64,              // 8 bytes
+    pub sol_amount: u64,         // 8 bytes
+    pub token_mint: Pubkey,      // 32 bytes
+    pub token_amount: u64,       // 8 bytes
+    pub sol_lots: Vec<Lot>,      // deposit times of the queued amounts, oldest first
+    pub token_lots: Vec<Lot>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct Lockup {
+    pub lock_period: i64,            // seconds each deposit stays locked; 0 = no lock
+    pub early_exit_penalty_bps: u16, // 0 = early withdrawals are refused
+    pub penalty_destination: PenaltyDestination,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PenaltyDestination {
+    #[default]
+    Depositors,
+    Treasury,
+}
+
+// Zero in any field leaves that limit off
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct DepositCaps {
+    pub sol_total: u64,
+    pub sol_per_depositor: u64,
+    pub token_per_depositor: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct DepositCapacity {
+    pub sol: u64,
+    pub token: u64,
 }
 
 #[account]
@@ -210,9 +1364,206 @@ pub struct Depositor {
     pub owner: Pubkey,           // 32 bytes
     pub is_initialized: bool,    // 1 byte  
     pub sol_amount: u64,         // 8 bytes
-    pub usdc_amount: u64,        // 8 bytes
-    pub usdc_mint: Pubkey,       // 32 bytes
+    pub token_amount: u64,       // 8 bytes
+    pub token_mint: Pubkey,      // 32 bytes
     pub deposit_time: i64,       // 8 bytes
+    pub sol_reward_debt: u128,   // 16 bytes
+    pub token_reward_debt: u128, // 16 bytes
+    pub sol_lots: Vec<Lot>,      // oldest first
+    pub token_lots: Vec<Lot>,    // oldest first
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct Lot {
+    pub amount: u64,
+    pub deposit_time: i64,
+}
+
+// Amount-weighted age of each balance in seconds
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct PositionAges {
+    pub sol: i64,
+    pub token: i64,
+}
+
+impl Depositor {
+    // Credit penalties shared out since each balance last changed
+    pub fn settle_sol_rewards(&mut self, reward_index: u128) -> Result<()> {
+        let pending = accrued_rewards(self.sol_amount, reward_index)?.saturating_sub(self.sol_reward_debt);
+        self.sol_amount = self.sol_amount.checked_add(pending as u64).ok_or(CustomError::MathOverflow)?;
+        credit_oldest_lot(&mut self.sol_lots, pending as u64)?;
+        self.sol_reward_debt = accrued_rewards(self.sol_amount, reward_index)?;
+        Ok(())
+    }
+
+    pub fn settle_token_rewards(&mut self, reward_index: u128) -> Result<()> {
+        let pending = accrued_rewards(self.token_amount, reward_index)?.saturating_sub(self.token_reward_debt);
+        self.token_amount = self.token_amount.checked_add(pending as u64).ok_or(CustomError::MathOverflow)?;
+        credit_oldest_lot(&mut self.token_lots, pending as u64)?;
+        self.token_reward_debt = accrued_rewards(self.token_amount, reward_index)?;
+        Ok(())
+    }
+}
+
+// Shared penalties are not deposits, so they join the oldest lot instead of starting a new lock
+fn credit_oldest_lot(lots: &mut [Lot], amount: u64) -> Result<()> {
+    if let Some(oldest) = lots.first_mut() {
+        oldest.amount = oldest.amount.checked_add(amount).ok_or(CustomError::MathOverflow)?;
+    }
+    Ok(())
+}
+
+#[event]
+pub struct ConfigInitialized {
+    pub config: Pubkey,
+    pub admin: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct LockupUpdated {
+    pub admin: Pubkey,
+    pub lockup: Lockup,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct MintConfigured {
+    pub admin: Pubkey,
+    pub mint: Pubkey,
+    pub enabled: bool,
+    pub deposit_cap: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct DepositCapsUpdated {
+    pub admin: Pubkey,
+    pub caps: DepositCaps,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PauseUpdated {
+    pub admin: Pubkey,
+    pub deposits_paused: bool,
+    pub withdrawals_paused: bool,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SolDeposited {
+    pub user: Pubkey,
+    pub amount: u64,
+    pub balance: u64,         // depositor SOL balance afterwards
+    pub total_sol: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct TokenDeposited {
+    pub user: Pubkey,
+    pub mint: Pubkey,
+    pub requested: u64,
+    pub received: u64,        // measured increase of the vault token account
+    pub balance: u64,         // depositor token balance afterwards
+    pub total_deposited: u64, // across all depositors of this mint
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct Withdrawn {
+    pub user: Pubkey,
+    pub sol_amount: u64,
+    pub sol_paid: u64,        // after any early-exit penalty
+    pub token_mint: Pubkey,
+    pub token_amount: u64,
+    pub token_paid: u64,
+    pub sol_balance: u64,
+    pub token_balance: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct WithdrawalRequested {
+    pub user: Pubkey,
+    pub epoch: u64,
+    pub sol_amount: u64,
+    pub token_mint: Pubkey,
+    pub token_amount: u64,
+    pub queued_sol: u64,      // request totals afterwards
+    pub queued_token: u64,
+    pub sol_balance: u64,
+    pub token_balance: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct WithdrawalCancelled {
+    pub user: Pubkey,
+    pub epoch: u64,
+    pub sol_amount: u64,
+    pub token_mint: Pubkey,
+    pub token_amount: u64,
+    pub sol_balance: u64,
+    pub token_balance: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct WithdrawalsSettled {
+    pub admin: Pubkey,
+    pub epoch: u64,
+    pub queued_sol: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct WithdrawalClaimed {
+    pub user: Pubkey,
+    pub epoch: u64,
+    pub sol_amount: u64,
+    pub token_mint: Pubkey,
+    pub token_amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct TreasuryWithdrawn {
+    pub admin: Pubkey,
+    pub sol_amount: u64,
+    pub token_mint: Pubkey,
+    pub token_amount: u64,
+    pub vault_sol_balance: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct TokenAuthorityMigrated {
+    pub admin: Pubkey,
+    pub mint: Pubkey,
+    pub token_account: Pubkey,
+    pub authority: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct DepositorMigrated {
+    pub user: Pubkey,
+    pub sol_amount: u64,
+    pub token_mint: Pubkey,
+    pub token_amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct LegacyTokenAccountMigrated {
+    pub admin: Pubkey,
+    pub mint: Pubkey,
+    pub legacy_account: Pubkey,
+    pub token_account: Pubkey,
+    pub amount: u64,
+    pub timestamp: i64,
 }
 
 #[error_code]
@@ -225,4 +1576,30 @@ pub enum CustomError {
     InvalidMint,
     #[msg("Math overflow")]
     MathOverflow,
+    #[msg("Vault is paused")]
+    Paused,
+    #[msg("Deposit cap exceeded")]
+    DepositCapExceeded,
+    #[msg("Mint is disabled")]
+    MintDisabled,
+    #[msg("Too many accepted mints")]
+    TooManyMints,
+    #[msg("Mint uses an unsupported Token-2022 extension")]
+    UnsupportedMintExtension,
+    #[msg("Deposit is still locked")]
+    Locked,
+    #[msg("Invalid lockup configuration")]
+    InvalidLockup,
+    #[msg("Claim the earlier withdrawal request first")]
+    WithdrawalPending,
+    #[msg("Withdrawal epoch already settled")]
+    WithdrawalSettled,
+    #[msg("Withdrawal epoch not settled yet")]
+    WithdrawalNotSettled,
+    #[msg("Account is not in its deployed layout")]
+    NotLegacyAccount,
+    #[msg("Legacy token account has not been migrated yet")]
+    LegacyTokensNotMigrated,
+    #[msg("Token accounts are required to move tokens")]
+    TokenAccountsRequired,
 }
\ No newline at end of file
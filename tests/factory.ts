@@ -0,0 +1,473 @@
+import * as anchor from "@coral-xyz/anchor";
+import { BN } from "@coral-xyz/anchor";
+import {
+  Keypair,
+  LAMPORTS_PER_SOL,
+  PublicKey,
+  SystemProgram,
+  Transaction,
+  TransactionInstruction,
+} from "@solana/web3.js";
+import { expect } from "chai";
+import fs from "fs";
+
+// Runs against a local validator: `anchor test --provider.cluster localnet`
+const provider = anchor.AnchorProvider.env();
+anchor.setProvider(provider);
+
+const idl = JSON.parse(fs.readFileSync("target/idl/factory.json", "utf8"));
+const program = new anchor.Program(idl, provider);
+
+const { TOKEN_PROGRAM_ID, ASSOCIATED_PROGRAM_ID, associatedAddress } = anchor.utils.token;
+const NATIVE_MINT = new PublicKey("So11111111111111111111111111111111111111112");
+const BPF_LOADER_UPGRADEABLE = new PublicKey("BPFLoaderUpgradeab1e11111111111111111111111");
+const VAULTS_PER_PAGE = 30;
+
+const pda = (...seeds: (Buffer | Uint8Array)[]) =>
+  PublicKey.findProgramAddressSync(seeds, program.programId)[0];
+const u32 = (n: number) => new BN(n).toArrayLike(Buffer, "le", 4);
+const u64 = (n: number | BN) => new BN(n).toArrayLike(Buffer, "le", 8);
+const sleep = (ms: number) => new Promise((resolve) => setTimeout(resolve, ms));
+
+const factory = pda(Buffer.from("vault_factory"));
+const mintRegistry = pda(Buffer.from("mint_registry"));
+const owner = provider.wallet.publicKey;
+
+async function expectError(promise: Promise<unknown>, code: string) {
+  try {
+    await promise;
+  } catch (err) {
+    expect(err.error?.errorCode?.code ?? String(err)).to.contain(code);
+    return;
+  }
+  expect.fail(`expected ${code}`);
+}
+
+async function funded(): Promise<Keypair> {
+  const keypair = Keypair.generate();
+  const signature = await provider.connection.requestAirdrop(keypair.publicKey, 20 * LAMPORTS_PER_SOL);
+  await provider.connection.confirmTransaction(signature, "confirmed");
+  return keypair;
+}
+
+// Plain SPL token instructions, so the tests need nothing beyond anchor and web3
+async function createMint(decimals: number): Promise<PublicKey> {
+  const mint = Keypair.generate();
+  const data = Buffer.concat([Buffer.from([20, decimals]), owner.toBuffer(), Buffer.from([0])]);
+  const tx = new Transaction().add(
+    SystemProgram.createAccount({
+      fromPubkey: owner,
+      newAccountPubkey: mint.publicKey,
+      lamports: await provider.connection.getMinimumBalanceForRentExemption(82),
+      space: 82,
+      programId: TOKEN_PROGRAM_ID,
+    }),
+    new TransactionInstruction({
+      programId: TOKEN_PROGRAM_ID,
+      keys: [{ pubkey: mint.publicKey, isSigner: false, isWritable: true }],
+      data,
+    })
+  );
+  await provider.sendAndConfirm(tx, [mint]);
+  return mint.publicKey;
+}
+
+async function createAta(mint: PublicKey, user: PublicKey): Promise<PublicKey> {
+  const ata = associatedAddress({ mint, owner: user });
+  const tx = new Transaction().add(
+    new TransactionInstruction({
+      programId: ASSOCIATED_PROGRAM_ID,
+      keys: [
+        { pubkey: owner, isSigner: true, isWritable: true },
+        { pubkey: ata, isSigner: false, isWritable: true },
+        { pubkey: user, isSigner: false, isWritable: false },
+        { pubkey: mint, isSigner: false, isWritable: false },
+        { pubkey: SystemProgram.programId, isSigner: false, isWritable: false },
+        { pubkey: TOKEN_PROGRAM_ID, isSigner: false, isWritable: false },
+      ],
+      data: Buffer.from([1]),
+    })
+  );
+  await provider.sendAndConfirm(tx);
+  return ata;
+}
+
+async function transferTokens(from: Keypair, source: PublicKey, destination: PublicKey, amount: BN) {
+  const tx = new Transaction().add(
+    new TransactionInstruction({
+      programId: TOKEN_PROGRAM_ID,
+      keys: [
+        { pubkey: source, isSigner: false, isWritable: true },
+        { pubkey: destination, isSigner: false, isWritable: true },
+        { pubkey: from.publicKey, isSigner: true, isWritable: false },
+      ],
+      data: Buffer.concat([Buffer.from([3]), u64(amount)]),
+    })
+  );
+  await provider.sendAndConfirm(tx, [from]);
+}
+
+async function tokenBalance(account: PublicKey): Promise<BN> {
+  return new BN((await provider.connection.getTokenAccountBalance(account)).value.amount);
+}
+
+describe("factory", () => {
+  let tokenMint: PublicKey;
+
+  before(async () => {
+    await program.methods
+      .initializeFactory()
+      .accountsPartial({
+        factory,
+        owner,
+        programData: PublicKey.findProgramAddressSync([program.programId.toBuffer()], BPF_LOADER_UPGRADEABLE)[0],
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+    await program.methods
+      .initializeMintRegistry()
+      .accountsPartial({ factory, mintRegistry, owner, systemProgram: SystemProgram.programId })
+      .rpc();
+
+    tokenMint = await createMint(6);
+    await program.methods
+      .configureMint(true, new BN(0))
+      .accountsPartial({ factory, mintRegistry, mint: tokenMint, owner })
+      .rpc();
+  });
+
+  async function createVault(manager: PublicKey): Promise<PublicKey> {
+    const managerProfile = pda(Buffer.from("manager_profile"), manager.toBuffer());
+    const profile = await program.account.managerProfile.fetchNullable(managerProfile);
+    const nonce = profile ? (profile.nextNonce as number) : 0;
+    const vault = pda(Buffer.from("vault"), manager.toBuffer(), u32(nonce));
+    const { vaultCount } = await program.account.factory.fetch(factory);
+
+    await program.methods
+      .createVault(manager, tokenMint)
+      .accountsPartial({
+        factory,
+        mintRegistry,
+        owner,
+        managerProfile,
+        vault,
+        registryPage: pda(Buffer.from("vault_registry_page"), factory.toBuffer(), u32(Math.floor(vaultCount / VAULTS_PER_PAGE))),
+        previousPage: null,
+        vaultSolAccount: pda(Buffer.from("vault_sol"), vault.toBuffer()),
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+    return vault;
+  }
+
+  const solShareMint = (vault: PublicKey) => pda(Buffer.from("share_mint"), vault.toBuffer(), NATIVE_MINT.toBuffer());
+  const shareAccount = (vault: PublicKey, user: PublicKey) =>
+    associatedAddress({ mint: solShareMint(vault), owner: user });
+
+  function positionAccounts(vault: PublicKey, user: PublicKey) {
+    return {
+      user,
+      factory,
+      vault,
+      depositor: pda(Buffer.from("depositor"), vault.toBuffer(), user.toBuffer()),
+      userPositions: pda(Buffer.from("user_positions"), user.toBuffer()),
+      vaultSolAccount: pda(Buffer.from("vault_sol"), vault.toBuffer()),
+      shareMint: solShareMint(vault),
+      userShareAccount: shareAccount(vault, user),
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: SystemProgram.programId,
+    };
+  }
+
+  async function depositSol(vault: PublicKey, user: Keypair, lamports: number) {
+    await program.methods
+      .depositSol(new BN(lamports), [])
+      .accountsPartial({
+        ...positionAccounts(vault, user.publicKey),
+        vaultAuthority: pda(Buffer.from("vault_authority"), vault.toBuffer()),
+        associatedTokenProgram: ASSOCIATED_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+  }
+
+  function withdrawSol(vault: PublicKey, user: Keypair, shares: BN) {
+    return program.methods
+      .withdrawSol(shares)
+      .accountsPartial(positionAccounts(vault, user.publicKey))
+      .signers([user])
+      .rpc();
+  }
+
+  async function queueAccounts(vault: PublicKey, user: PublicKey) {
+    const { solQueue } = await program.account.vault.fetch(vault);
+    return {
+      withdrawalRequest: pda(Buffer.from("withdrawal_request"), vault.toBuffer(), NATIVE_MINT.toBuffer(), user.toBuffer()),
+      withdrawalEpoch: pda(Buffer.from("withdrawal_epoch"), vault.toBuffer(), NATIVE_MINT.toBuffer(), u64(solQueue.epoch)),
+      escrowShareAccount: pda(Buffer.from("withdrawal_escrow"), vault.toBuffer(), NATIVE_MINT.toBuffer()),
+      vaultAuthority: pda(Buffer.from("vault_authority"), vault.toBuffer()),
+    };
+  }
+
+  async function requestWithdraw(vault: PublicKey, user: Keypair, shares: BN) {
+    const { vaultSolAccount, ...accounts } = positionAccounts(vault, user.publicKey);
+    await program.methods
+      .requestWithdraw(shares)
+      .accountsPartial({
+        ...accounts,
+        ...(await queueAccounts(vault, user.publicKey)),
+        assetMint: NATIVE_MINT,
+        custody: vaultSolAccount,
+      })
+      .signers([user])
+      .rpc();
+  }
+
+  function redeemable(vault: PublicKey, shares: BN): Promise<BN> {
+    return program.methods
+      .previewRedeem(shares)
+      .accountsPartial({
+        vault,
+        assetMint: NATIVE_MINT,
+        shareMint: solShareMint(vault),
+        custody: pda(Buffer.from("vault_sol"), vault.toBuffer()),
+      })
+      .view();
+  }
+
+  function setLockup(vault: PublicKey, manager: Keypair | null, lockPeriod: number, earlyExitPenaltyBps: number) {
+    const lockup = { lockPeriod: new BN(lockPeriod), earlyExitPenaltyBps, penaltyDestination: { depositors: {} } };
+    const call = program.methods
+      .setLockup(lockup)
+      .accountsPartial({ vault, manager: manager ? manager.publicKey : owner });
+    return manager ? call.signers([manager]).rpc() : call.rpc();
+  }
+
+  function closeVault(vault: PublicKey, manager: Keypair) {
+    return program.methods
+      .closeVault()
+      .accountsPartial({
+        manager: manager.publicKey,
+        factory,
+        vault,
+        registryPage: pda(Buffer.from("vault_registry_page"), factory.toBuffer(), u32(0)),
+        managerProfile: pda(Buffer.from("manager_profile"), manager.publicKey.toBuffer()),
+        vaultSolAccount: pda(Buffer.from("vault_sol"), vault.toBuffer()),
+        vaultAuthority: pda(Buffer.from("vault_authority"), vault.toBuffer()),
+        solShareMint: solShareMint(vault),
+        tokenShareMint: pda(Buffer.from("share_mint"), vault.toBuffer(), tokenMint.toBuffer()),
+        solEscrow: pda(Buffer.from("withdrawal_escrow"), vault.toBuffer(), NATIVE_MINT.toBuffer()),
+        tokenEscrow: pda(Buffer.from("withdrawal_escrow"), vault.toBuffer(), tokenMint.toBuffer()),
+        vaultTokenAccount: pda(Buffer.from("vault_token"), vault.toBuffer()),
+        managerTokenAccount: null,
+        tokenMint,
+        assetTokenProgram: TOKEN_PROGRAM_ID,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([manager])
+      .rpc();
+  }
+
+  function rotateManager(vault: PublicKey, previous: PublicKey | null, next: PublicKey) {
+    return program.methods
+      .rotateManager(next)
+      .accountsPartial({
+        factory,
+        vault,
+        previousProfile: previous ? pda(Buffer.from("manager_profile"), previous.toBuffer()) : null,
+        managerProfile: pda(Buffer.from("manager_profile"), next.toBuffer()),
+        authority: owner,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+  }
+
+  describe("lockup", () => {
+    it("refuses early exits, including for shares received by transfer", async () => {
+      const vault = await createVault(owner);
+      await setLockup(vault, null, 3600, 0);
+      const alice = await funded();
+      const carol = await funded();
+
+      await depositSol(vault, alice, LAMPORTS_PER_SOL);
+      const shares = await tokenBalance(shareAccount(vault, alice.publicKey));
+      await expectError(withdrawSol(vault, alice, shares), "Locked");
+
+      const carolShares = await createAta(solShareMint(vault), carol.publicKey);
+      await transferTokens(alice, shareAccount(vault, alice.publicKey), carolShares, shares);
+      await expectError(withdrawSol(vault, carol, shares), "Locked");
+    });
+
+    it("charges the early exit penalty on the locked part", async () => {
+      const vault = await createVault(owner);
+      await setLockup(vault, null, 3600, 1000);
+      const alice = await funded();
+
+      await depositSol(vault, alice, LAMPORTS_PER_SOL);
+      const shares = await tokenBalance(shareAccount(vault, alice.publicKey));
+      const custody = pda(Buffer.from("vault_sol"), vault.toBuffer());
+      const custodyBefore = await provider.connection.getBalance(custody);
+
+      await withdrawSol(vault, alice, shares);
+      const paid = custodyBefore - (await provider.connection.getBalance(custody));
+      expect(paid).to.be.closeTo(0.9 * LAMPORTS_PER_SOL, 1);
+      expect((await tokenBalance(shareAccount(vault, alice.publicKey))).toNumber()).to.equal(0);
+    });
+  });
+
+  describe("withdrawal queue", () => {
+    it("keeps settled but unclaimed withdrawals out of the share price", async () => {
+      const vault = await createVault(owner);
+      const alice = await funded();
+      const bob = await funded();
+
+      await depositSol(vault, alice, LAMPORTS_PER_SOL);
+      await depositSol(vault, bob, LAMPORTS_PER_SOL);
+      const bobShares = await tokenBalance(shareAccount(vault, bob.publicKey));
+      const before = await redeemable(vault, bobShares);
+
+      const aliceShares = await tokenBalance(shareAccount(vault, alice.publicKey));
+      await requestWithdraw(vault, alice, aliceShares);
+      const queue = await queueAccounts(vault, alice.publicKey);
+      await program.methods
+        .settleWithdrawals()
+        .accountsPartial({
+          factory,
+          mintRegistry,
+          vault,
+          withdrawalEpoch: queue.withdrawalEpoch,
+          assetMint: NATIVE_MINT,
+          custody: pda(Buffer.from("vault_sol"), vault.toBuffer()),
+          vaultAuthority: queue.vaultAuthority,
+          shareMint: solShareMint(vault),
+          escrowShareAccount: queue.escrowShareAccount,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .rpc();
+
+      const { solQueue } = await program.account.vault.fetch(vault);
+      expect(solQueue.reservedAssets.toNumber()).to.be.closeTo(LAMPORTS_PER_SOL, 1);
+      expect((await redeemable(vault, bobShares)).toNumber()).to.be.closeTo(before.toNumber(), 1);
+
+      // A new deposit is priced as if the reserved SOL had already left
+      await depositSol(vault, bob, LAMPORTS_PER_SOL);
+      const minted = (await tokenBalance(shareAccount(vault, bob.publicKey))).sub(bobShares);
+      expect(minted.toNumber()).to.be.closeTo(bobShares.toNumber(), 1);
+
+      const custody = pda(Buffer.from("vault_sol"), vault.toBuffer());
+      const custodyBefore = await provider.connection.getBalance(custody);
+      await program.methods
+        .claimWithdrawalSol()
+        .accountsPartial({
+          user: alice.publicKey,
+          factory,
+          vault,
+          depositor: pda(Buffer.from("depositor"), vault.toBuffer(), alice.publicKey.toBuffer()),
+          withdrawalRequest: queue.withdrawalRequest,
+          withdrawalEpoch: queue.withdrawalEpoch,
+          vaultSolAccount: custody,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([alice])
+        .rpc();
+      expect(custodyBefore - (await provider.connection.getBalance(custody))).to.be.closeTo(LAMPORTS_PER_SOL, 1);
+      expect((await program.account.vault.fetch(vault)).solQueue.reservedAssets.toNumber()).to.equal(0);
+    });
+
+    it("keeps the original deposit time when a withdrawal request is cancelled", async () => {
+      const vault = await createVault(owner);
+      await setLockup(vault, null, 2, 0);
+      const alice = await funded();
+
+      await depositSol(vault, alice, LAMPORTS_PER_SOL);
+      await sleep(4000);
+      const shares = await tokenBalance(shareAccount(vault, alice.publicKey));
+      await requestWithdraw(vault, alice, shares);
+
+      const queue = await queueAccounts(vault, alice.publicKey);
+      const request = await program.account.withdrawalRequest.fetch(queue.withdrawalRequest);
+      await program.methods
+        .cancelWithdrawal()
+        .accountsPartial({
+          user: alice.publicKey,
+          factory,
+          vault,
+          depositor: pda(Buffer.from("depositor"), vault.toBuffer(), alice.publicKey.toBuffer()),
+          withdrawalRequest: queue.withdrawalRequest,
+          withdrawalEpoch: pda(Buffer.from("withdrawal_epoch"), vault.toBuffer(), NATIVE_MINT.toBuffer(), u64(request.epoch)),
+          vaultAuthority: queue.vaultAuthority,
+          escrowShareAccount: queue.escrowShareAccount,
+          userShareAccount: shareAccount(vault, alice.publicKey),
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([alice])
+        .rpc();
+
+      await withdrawSol(vault, alice, shares);
+    });
+  });
+
+  describe("closing", () => {
+    it("closes only once every depositor has been paid, waiving locks while closing", async () => {
+      const manager = await funded();
+      const vault = await createVault(manager.publicKey);
+      await setLockup(vault, manager, 3600, 0);
+      const alice = await funded();
+      await depositSol(vault, alice, LAMPORTS_PER_SOL);
+
+      await program.methods.beginClose().accountsPartial({ factory, vault, authority: owner }).rpc();
+      await expectError(depositSol(vault, alice, LAMPORTS_PER_SOL), "VaultClosing");
+      await expectError(closeVault(vault, manager), "OutstandingLiabilities");
+
+      await withdrawSol(vault, alice, await tokenBalance(shareAccount(vault, alice.publicKey)));
+      await closeVault(vault, manager);
+
+      expect(await provider.connection.getAccountInfo(vault)).to.be.null;
+      const profile = await program.account.managerProfile.fetch(pda(Buffer.from("manager_profile"), manager.publicKey.toBuffer()));
+      expect(profile.vaults.map((key: PublicKey) => key.toBase58())).to.not.include(vault.toBase58());
+    });
+  });
+
+  describe("manager rotation", () => {
+    it("moves the vault between manager profiles on rotation", async () => {
+      const manager = await funded();
+      const next = Keypair.generate();
+      const vault = await createVault(manager.publicKey);
+
+      await rotateManager(vault, manager.publicKey, next.publicKey);
+
+      expect((await program.account.vault.fetch(vault)).manager.toBase58()).to.equal(next.publicKey.toBase58());
+      const previous = await program.account.managerProfile.fetch(pda(Buffer.from("manager_profile"), manager.publicKey.toBuffer()));
+      const current = await program.account.managerProfile.fetch(pda(Buffer.from("manager_profile"), next.publicKey.toBuffer()));
+      expect(previous.vaults.map((key: PublicKey) => key.toBase58())).to.not.include(vault.toBase58());
+      expect(current.vaults.map((key: PublicKey) => key.toBase58())).to.include(vault.toBase58());
+    });
+
+    it("lets the factory owner hand a renounced vault to a new manager who can close it", async () => {
+      const manager = await funded();
+      const next = await funded();
+      const vault = await createVault(manager.publicKey);
+
+      await program.methods
+        .renounceManager()
+        .accountsPartial({
+          vault,
+          managerProfile: pda(Buffer.from("manager_profile"), manager.publicKey.toBuffer()),
+          authority: manager.publicKey,
+        })
+        .signers([manager])
+        .rpc();
+      expect((await program.account.vault.fetch(vault)).manager.toBase58()).to.equal(PublicKey.default.toBase58());
+
+      await rotateManager(vault, null, next.publicKey);
+      await program.methods
+        .beginClose()
+        .accountsPartial({ factory, vault, authority: next.publicKey })
+        .signers([next])
+        .rpc();
+      await closeVault(vault, next);
+      expect(await provider.connection.getAccountInfo(vault)).to.be.null;
+    });
+  });
+});
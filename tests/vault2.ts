@@ -0,0 +1,244 @@
+import * as anchor from "@coral-xyz/anchor";
+import { BN } from "@coral-xyz/anchor";
+import {
+  Keypair,
+  LAMPORTS_PER_SOL,
+  PublicKey,
+  SystemProgram,
+  Transaction,
+  TransactionInstruction,
+} from "@solana/web3.js";
+import { expect } from "chai";
+import fs from "fs";
+
+// Runs against a local validator: `anchor test --provider.cluster localnet`
+const provider = anchor.AnchorProvider.env();
+anchor.setProvider(provider);
+
+const idl = JSON.parse(fs.readFileSync("target/idl/vault_version2.json", "utf8"));
+const program = new anchor.Program(idl, provider);
+
+const { TOKEN_PROGRAM_ID, ASSOCIATED_PROGRAM_ID, associatedAddress } = anchor.utils.token;
+const BPF_LOADER_UPGRADEABLE = new PublicKey("BPFLoaderUpgradeab1e11111111111111111111111");
+
+const pda = (...seeds: (Buffer | Uint8Array)[]) =>
+  PublicKey.findProgramAddressSync(seeds, program.programId)[0];
+const u64 = (n: number | BN) => new BN(n).toArrayLike(Buffer, "le", 8);
+
+const config = pda(Buffer.from("vault_config"));
+const vaultAuthority = pda(Buffer.from("vault_authority"), config.toBuffer());
+const vaultPda = pda(Buffer.from("vault_pda"));
+const admin = provider.wallet.publicKey;
+
+async function expectError(promise: Promise<unknown>, code: string) {
+  try {
+    await promise;
+  } catch (err) {
+    expect(err.error?.errorCode?.code ?? String(err)).to.contain(code);
+    return;
+  }
+  expect.fail(`expected ${code}`);
+}
+
+async function funded(): Promise<Keypair> {
+  const keypair = Keypair.generate();
+  const signature = await provider.connection.requestAirdrop(keypair.publicKey, 20 * LAMPORTS_PER_SOL);
+  await provider.connection.confirmTransaction(signature, "confirmed");
+  return keypair;
+}
+
+// Plain SPL token instructions, so the tests need nothing beyond anchor and web3
+async function createMint(decimals: number): Promise<PublicKey> {
+  const mint = Keypair.generate();
+  const data = Buffer.concat([Buffer.from([20, decimals]), admin.toBuffer(), Buffer.from([0])]);
+  const tx = new Transaction().add(
+    SystemProgram.createAccount({
+      fromPubkey: admin,
+      newAccountPubkey: mint.publicKey,
+      lamports: await provider.connection.getMinimumBalanceForRentExemption(82),
+      space: 82,
+      programId: TOKEN_PROGRAM_ID,
+    }),
+    new TransactionInstruction({
+      programId: TOKEN_PROGRAM_ID,
+      keys: [{ pubkey: mint.publicKey, isSigner: false, isWritable: true }],
+      data,
+    })
+  );
+  await provider.sendAndConfirm(tx, [mint]);
+  return mint.publicKey;
+}
+
+async function createFundedAta(mint: PublicKey, user: PublicKey, amount: number): Promise<PublicKey> {
+  const ata = associatedAddress({ mint, owner: user });
+  const tx = new Transaction().add(
+    new TransactionInstruction({
+      programId: ASSOCIATED_PROGRAM_ID,
+      keys: [
+        { pubkey: admin, isSigner: true, isWritable: true },
+        { pubkey: ata, isSigner: false, isWritable: true },
+        { pubkey: user, isSigner: false, isWritable: false },
+        { pubkey: mint, isSigner: false, isWritable: false },
+        { pubkey: SystemProgram.programId, isSigner: false, isWritable: false },
+        { pubkey: TOKEN_PROGRAM_ID, isSigner: false, isWritable: false },
+      ],
+      data: Buffer.from([1]),
+    }),
+    new TransactionInstruction({
+      programId: TOKEN_PROGRAM_ID,
+      keys: [
+        { pubkey: mint, isSigner: false, isWritable: true },
+        { pubkey: ata, isSigner: false, isWritable: true },
+        { pubkey: admin, isSigner: true, isWritable: false },
+      ],
+      data: Buffer.concat([Buffer.from([7]), u64(amount)]),
+    })
+  );
+  await provider.sendAndConfirm(tx);
+  return ata;
+}
+
+describe("vault_version2", () => {
+  let tokenMint: PublicKey;
+  let alice: Keypair;
+  let aliceTokenAccount: PublicKey;
+  let depositor: PublicKey;
+  let withdrawalRequest: PublicKey;
+  let vaultTokenAccount: PublicKey;
+
+  before(async () => {
+    await program.methods
+      .initializeConfig()
+      .accountsPartial({
+        config,
+        vaultAuthority,
+        admin,
+        vaultPda,
+        programData: PublicKey.findProgramAddressSync([program.programId.toBuffer()], BPF_LOADER_UPGRADEABLE)[0],
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    tokenMint = await createMint(6);
+    await program.methods.configureMint(true, new BN(0)).accountsPartial({ config, mint: tokenMint, admin }).rpc();
+
+    alice = await funded();
+    aliceTokenAccount = await createFundedAta(tokenMint, alice.publicKey, 1_000_000);
+    depositor = pda(Buffer.from("depositor"), alice.publicKey.toBuffer());
+    withdrawalRequest = pda(Buffer.from("withdrawal_request"), alice.publicKey.toBuffer());
+    vaultTokenAccount = pda(Buffer.from("vault_token_account"), tokenMint.toBuffer());
+
+    await program.methods
+      .depositToken(new BN(100_000))
+      .accountsPartial({
+        user: alice.publicKey,
+        config,
+        depositor,
+        vaultAuthority,
+        vaultTokenAccount,
+        userTokenAccount: aliceTokenAccount,
+        tokenMint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([alice])
+      .rpc();
+    await program.methods
+      .depositSol(new BN(2 * LAMPORTS_PER_SOL))
+      .accountsPartial({ user: alice.publicKey, config, depositor, vaultPda, systemProgram: SystemProgram.programId })
+      .signers([alice])
+      .rpc();
+  });
+
+  const setPause = (depositsPaused: boolean, withdrawalsPaused: boolean) =>
+    program.methods.setPause(depositsPaused, withdrawalsPaused).accountsPartial({ config, admin }).rpc();
+
+  const withdraw = (solAmount: number, tokenAmount: number) =>
+    program.methods
+      .withdraw(new BN(solAmount), new BN(tokenAmount))
+      .accountsPartial({
+        user: alice.publicKey,
+        config,
+        depositor,
+        vaultPda,
+        vaultAuthority,
+        vaultTokenAccount,
+        userTokenAccount: aliceTokenAccount,
+        tokenMint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([alice])
+      .rpc();
+
+  // SOL only, so no mint is passed
+  const requestSolWithdraw = (solAmount: number) =>
+    program.methods
+      .requestWithdraw(new BN(solAmount), new BN(0))
+      .accountsPartial({
+        user: alice.publicKey,
+        config,
+        depositor,
+        withdrawalRequest,
+        tokenMint: null,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([alice])
+      .rpc();
+
+  const cancelWithdrawal = () =>
+    program.methods
+      .cancelWithdrawal()
+      .accountsPartial({ user: alice.publicKey, config, depositor, withdrawalRequest })
+      .signers([alice])
+      .rpc();
+
+  const settleWithdrawals = () => program.methods.settleWithdrawals().accountsPartial({ config, admin }).rpc();
+
+  const claimSolWithdrawal = () =>
+    program.methods
+      .claimWithdrawal()
+      .accountsPartial({
+        user: alice.publicKey,
+        config,
+        withdrawalRequest,
+        vaultPda,
+        vaultAuthority,
+        vaultTokenAccount: null,
+        userTokenAccount: null,
+        tokenMint: null,
+        tokenProgram: null,
+      })
+      .signers([alice])
+      .rpc();
+
+  it("refuses direct withdrawals and new requests while withdrawals are paused", async () => {
+    await setPause(false, true);
+    await expectError(withdraw(LAMPORTS_PER_SOL / 10, 0), "Paused");
+    await expectError(withdraw(0, 10_000), "Paused");
+    await expectError(requestSolWithdraw(LAMPORTS_PER_SOL / 2), "Paused");
+
+    await setPause(false, false);
+    await withdraw(LAMPORTS_PER_SOL / 10, 10_000);
+  });
+
+  it("holds queued withdrawals while paused and claims SOL without token accounts", async () => {
+    await requestSolWithdraw(LAMPORTS_PER_SOL / 2);
+
+    await setPause(false, true);
+    await expectError(cancelWithdrawal(), "Paused");
+    await expectError(settleWithdrawals(), "Paused");
+
+    await setPause(false, false);
+    await settleWithdrawals();
+
+    await setPause(false, true);
+    await expectError(claimSolWithdrawal(), "Paused");
+
+    await setPause(false, false);
+    const before = await provider.connection.getBalance(alice.publicKey);
+    await claimSolWithdrawal();
+    // The claim also refunds the request's rent
+    expect((await provider.connection.getBalance(alice.publicKey)) - before).to.be.at.least(LAMPORTS_PER_SOL / 2 - 10_000);
+    expect(await provider.connection.getAccountInfo(withdrawalRequest)).to.be.null;
+  });
+});
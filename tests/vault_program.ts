@@ -0,0 +1,141 @@
+import * as anchor from "@coral-xyz/anchor";
+import { Keypair, LAMPORTS_PER_SOL, PublicKey, SystemProgram } from "@solana/web3.js";
+import { expect } from "chai";
+import fs from "fs";
+
+// Runs against a local validator: `anchor test --provider.cluster localnet`. The vault starts out
+// in its deployed layout, loaded from tests/fixtures through Anchor.toml: the legacy owner's vault,
+// custody still holding 2 SOL as a system account, and one position of 2 SOL.
+const provider = anchor.AnchorProvider.env();
+anchor.setProvider(provider);
+
+const idl = JSON.parse(fs.readFileSync("target/idl/vault.json", "utf8"));
+const program = new anchor.Program(idl, provider);
+
+const pda = (...seeds: (Buffer | Uint8Array)[]) =>
+  PublicKey.findProgramAddressSync(seeds, program.programId)[0];
+const fixtureKeypair = (name: string) =>
+  Keypair.fromSecretKey(Uint8Array.from(JSON.parse(fs.readFileSync(`tests/fixtures/${name}.json`, "utf8"))));
+
+const vault = pda(Buffer.from("vault"));
+const vaultPda = pda(Buffer.from("vault_pda"));
+const legacyOwner = fixtureKeypair("legacy-owner");
+const legacyDepositor = fixtureKeypair("legacy-depositor");
+const depositor = pda(Buffer.from("depositor"), legacyDepositor.publicKey.toBuffer());
+const LEGACY_BALANCE = 2 * LAMPORTS_PER_SOL;
+
+async function expectError(promise: Promise<unknown>, code: string) {
+  try {
+    await promise;
+  } catch (err) {
+    expect(err.error?.errorCode?.code ?? String(err)).to.contain(code);
+    return;
+  }
+  expect.fail(`expected ${code}`);
+}
+
+async function airdrop(to: PublicKey) {
+  const signature = await provider.connection.requestAirdrop(to, 10 * LAMPORTS_PER_SOL);
+  await provider.connection.confirmTransaction(signature, "confirmed");
+}
+
+function setPause(depositsPaused: boolean, withdrawalsPaused: boolean) {
+  return program.methods
+    .setPause(depositsPaused, withdrawalsPaused)
+    .accountsPartial({ owner: legacyOwner.publicKey, vault })
+    .signers([legacyOwner])
+    .rpc();
+}
+
+function withdraw(amount: number) {
+  return program.methods
+    .withdraw(new anchor.BN(amount))
+    .accountsPartial({ user: legacyDepositor.publicKey, vault, depositor, vaultPda, recipient: null })
+    .signers([legacyDepositor])
+    .rpc();
+}
+
+function closeVault() {
+  return program.methods
+    .closeVault()
+    .accountsPartial({ owner: legacyOwner.publicKey, vault, vaultPda })
+    .signers([legacyOwner])
+    .rpc();
+}
+
+describe("vault", () => {
+  before(async () => {
+    await airdrop(legacyOwner.publicKey);
+    await airdrop(legacyDepositor.publicKey);
+  });
+
+  it("books legacy custody as owed and keeps all of it payable once custody is adopted", async () => {
+    await program.methods
+      .migrateVault()
+      .accountsPartial({ owner: legacyOwner.publicKey, vault, vaultPda, systemProgram: SystemProgram.programId })
+      .signers([legacyOwner])
+      .rpc();
+    await program.methods
+      .migrateCustody()
+      .accountsPartial({ owner: legacyOwner.publicKey, vault, vaultPda, systemProgram: SystemProgram.programId })
+      .signers([legacyOwner])
+      .rpc();
+
+    const state = await program.account.vaultAccount.fetch(vault);
+    expect(state.totalLiabilities.toNumber()).to.equal(LEGACY_BALANCE);
+    expect(state.openPositions).to.equal(0);
+
+    const custody = await provider.connection.getAccountInfo(vaultPda);
+    const rent = await provider.connection.getMinimumBalanceForRentExemption(custody.data.length);
+    expect(custody.lamports - rent).to.equal(LEGACY_BALANCE);
+  });
+
+  it("refuses to close while legacy deposits are owed, even with no migrated positions", async () => {
+    await program.methods
+      .beginClose()
+      .accountsPartial({ owner: legacyOwner.publicKey, vault })
+      .signers([legacyOwner])
+      .rpc();
+
+    await expectError(closeVault(), "OutstandingLiabilities");
+  });
+
+  it("refuses withdrawals while paused and pays them again once unpaused", async () => {
+    await program.methods
+      .migrateDepositor()
+      .accountsPartial({ user: legacyDepositor.publicKey, vault, depositor, systemProgram: SystemProgram.programId })
+      .signers([legacyDepositor])
+      .rpc();
+
+    await setPause(false, true);
+    await expectError(withdraw(LAMPORTS_PER_SOL), "Paused");
+    await expectError(
+      program.methods
+        .withdrawAllAndClose()
+        .accountsPartial({ user: legacyDepositor.publicKey, vault, depositor, vaultPda, recipient: null })
+        .signers([legacyDepositor])
+        .rpc(),
+      "Paused"
+    );
+
+    await setPause(false, false);
+    const before = await provider.connection.getBalance(legacyDepositor.publicKey);
+    await withdraw(LAMPORTS_PER_SOL);
+    expect((await provider.connection.getBalance(legacyDepositor.publicKey)) - before).to.be.closeTo(LAMPORTS_PER_SOL, 10_000);
+  });
+
+  it("closes once the legacy deposits are paid out", async () => {
+    await expectError(closeVault(), "OutstandingLiabilities");
+
+    await program.methods
+      .withdrawAllAndClose()
+      .accountsPartial({ user: legacyDepositor.publicKey, vault, depositor, vaultPda, recipient: null })
+      .signers([legacyDepositor])
+      .rpc();
+    expect((await program.account.vaultAccount.fetch(vault)).totalLiabilities.toNumber()).to.equal(0);
+
+    await closeVault();
+    expect(await provider.connection.getAccountInfo(vault)).to.be.null;
+    expect(await provider.connection.getAccountInfo(vaultPda)).to.be.null;
+  });
+});